@@ -0,0 +1,468 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::{self, Journal, JournalEntry};
+use crate::layout::{Layout, Plan};
+use crate::{exact, scan, trash, DuplicateGroup, Error, ImageProperties, MatchKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// What happens to every duplicate that isn't kept.
+pub enum Action {
+    /// Move it into the `duplicates` directory.
+    Move,
+    /// Copy it into the `duplicates` directory, leaving the original alone.
+    Copy,
+    /// Replace it with a hard link to the keeper.
+    Hardlink,
+    /// Replace it with a symbolic link to the keeper.
+    Symlink,
+    /// Delete it outright.
+    Delete,
+    /// Move it into the freedesktop.org trash.
+    Trash,
+    /// Leave it alone, only report it.
+    None,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move" => Ok(Action::Move),
+            "copy" => Ok(Action::Copy),
+            "hardlink" => Ok(Action::Hardlink),
+            "symlink" => Ok(Action::Symlink),
+            "delete" => Ok(Action::Delete),
+            "trash" => Ok(Action::Trash),
+            "none" => Ok(Action::None),
+            _ => Err(format!(
+                "unknown action {:?}, expected one of: move, copy, hardlink, symlink, delete, trash, none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Action::Move => "move",
+            Action::Copy => "copy",
+            Action::Hardlink => "hardlink",
+            Action::Symlink => "symlink",
+            Action::Delete => "delete",
+            Action::Trash => "trash",
+            Action::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Action {
+    /// Whether this action puts duplicates (or copies of them) into a destination of their own.
+    pub fn relocates(&self) -> bool {
+        matches!(self, Action::Move | Action::Copy)
+    }
+
+    /// Whether this action replaces duplicates with links to their keeper, which only ever makes
+    /// sense for byte-identical copies: anything else about a duplicate would be gone for good.
+    pub fn links(&self) -> bool {
+        matches!(self, Action::Hardlink | Action::Symlink)
+    }
+
+    /// A short description of what this action does to `duplicate`, to go after "Would" or
+    /// "Failed to".
+    pub fn describe(
+        &self,
+        duplicate: &ImageProperties,
+        keeper: &ImageProperties,
+        destination: Option<&Path>,
+    ) -> String {
+        let destination = destination.unwrap_or_else(|| Path::new("?"));
+        match self {
+            Action::Move => format!("move {:?} to {:?}", duplicate.path, destination),
+            Action::Copy => format!("copy {:?} to {:?}", duplicate.path, destination),
+            Action::Hardlink => format!(
+                "replace {:?} with a hard link to {:?}",
+                duplicate.path, keeper.path
+            ),
+            Action::Symlink => format!(
+                "replace {:?} with a symlink to {:?}",
+                duplicate.path, keeper.path
+            ),
+            Action::Delete => format!("delete {:?}", duplicate.path),
+            Action::Trash => format!("move {:?} to the trash", duplicate.path),
+            Action::None => format!("leave {:?} alone", duplicate.path),
+        }
+    }
+
+    /// Applies this action to `duplicate`, which is a duplicate of `keeper`, returning where it (or
+    /// its copy) ended up if it ended up anywhere new. `destination` is where actions that
+    /// relocate duplicates put them, nothing already there is ever overwritten.
+    pub fn apply(
+        &self,
+        duplicate: &ImageProperties,
+        keeper: &ImageProperties,
+        destination: Option<&Path>,
+    ) -> Result<Option<PathBuf>, io::Error> {
+        match self {
+            Action::Move | Action::Copy => {
+                let destination = destination.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "no destination for this file")
+                })?;
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if *self == Action::Move {
+                    // rename happily replaces whatever is there, so check right before
+                    if destination.symlink_metadata().is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{:?} already exists", destination),
+                        ));
+                    }
+                    move_file(&duplicate.path, destination)?;
+                } else {
+                    copy_new(&duplicate.path, destination)?;
+                }
+                Ok(Some(destination.to_path_buf()))
+            }
+            Action::Hardlink | Action::Symlink
+                if !exact::is_identical(&duplicate.path, &keeper.path) =>
+            {
+                Err(not_a_copy(duplicate, keeper))
+            }
+            Action::Hardlink => {
+                // Renaming a link over another link to the same file does nothing at all, which
+                // would leave the temporary link behind
+                if !is_same_file(&duplicate.path, &keeper.path)? {
+                    replace_with_link(&duplicate.path, |tmp| fs::hard_link(&keeper.path, tmp))?;
+                }
+                Ok(None)
+            }
+            Action::Symlink => {
+                // Relative targets would be resolved against the duplicate's directory instead
+                let target = fs::canonicalize(&keeper.path)?;
+                replace_with_link(&duplicate.path, |tmp| symlink(&target, tmp)).map(|_| None)
+            }
+            Action::Delete => fs::remove_file(&duplicate.path).map(|_| None),
+            Action::Trash => trash::move_to_trash(&duplicate.path).map(Some),
+            Action::None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Applies an `Action` to every duplicate of a set of groups, putting duplicates where its `Plan`
+/// says and recording every change in its `Journal`, if it has one.
+pub struct Executor {
+    action: Action,
+    plan: Plan,
+    journal: Option<Journal>,
+    /// Every time recording a change in the journal failed.
+    journal_errors: Vec<io::Error>,
+    dry_run: bool,
+}
+
+impl Executor {
+    /// An executor for the duplicates of `groups`, see `Plan::new` for where they go.
+    pub fn new(
+        groups: &[DuplicateGroup],
+        action: Action,
+        layout: Layout,
+        output_dir: Option<&Path>,
+    ) -> Executor {
+        Executor {
+            action,
+            plan: Plan::new(groups, action, layout, output_dir),
+            journal: None,
+            journal_errors: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    /// Records every change in `journal`, so they can be undone with `journal::undo`.
+    pub fn with_journal(mut self, journal: Journal) -> Executor {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Only goes through the motions, every duplicate is reported as done without being touched.
+    pub fn dry_run(mut self, dry_run: bool) -> Executor {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Why recording changes in the journal failed, for every change that's missing from it. The
+    /// changes themselves were made all the same.
+    pub fn journal_errors(&self) -> &[io::Error] {
+        &self.journal_errors
+    }
+
+    /// What this executor does (or did) to `duplicate`, see `Action::describe`.
+    pub fn describe(&self, duplicate: &ImageProperties, keeper: &ImageProperties) -> String {
+        self.action
+            .describe(duplicate, keeper, self.plan.destination(duplicate))
+    }
+
+    /// Applies the action to every duplicate in `groups`, which should be the groups the executor
+    /// was made for. `done` is called for each of them with its group, what was done to it (see
+    /// `describe`) and whether that worked.
+    ///
+    /// Images in more than one group are only acted on once, and always against the image that
+    /// ends up kept, which needn't be the keeper of their own group (see `final_keepers`). Link
+    /// actions fail for every duplicate that isn't an exact copy, so the images of other groups
+    /// are only ever linked to images that stay put.
+    pub fn execute<F>(&mut self, groups: &[DuplicateGroup], mut done: F)
+    where
+        F: FnMut(&DuplicateGroup, &ImageProperties, &str, Result<(), Error>),
+    {
+        let keepers = final_keepers(groups);
+        let mut handled = HashSet::new();
+        for group in groups {
+            for duplicate in group.duplicates() {
+                if !keepers.contains_key(duplicate.path.as_path())
+                    || !handled.insert(&duplicate.path)
+                {
+                    continue;
+                }
+                let (keeper, kind) = keepers[duplicate.path.as_path()];
+                let keeper = match self.action.links() {
+                    true => resolve_keeper(&keepers, keeper, |kind| kind == MatchKind::Exact),
+                    false => resolve_keeper(&keepers, keeper, |_| true),
+                };
+                let description = self.describe(duplicate, keeper);
+                let result = if self.action.links() && kind != MatchKind::Exact {
+                    Err(Error::Action {
+                        description: description.clone(),
+                        source: not_a_copy(duplicate, keeper),
+                    })
+                } else if self.dry_run {
+                    Ok(())
+                } else {
+                    self.apply(duplicate, keeper)
+                        .map_err(|source| Error::Action {
+                            description: description.clone(),
+                            source,
+                        })
+                };
+                done(group, duplicate, &description, result);
+            }
+        }
+    }
+
+    /// Applies the action to a single duplicate, recording the change in the journal.
+    ///
+    /// Failing to write the journal is kept in `journal_errors`, but doesn't count as failing the
+    /// action, since the change was already made by then.
+    fn apply(
+        &mut self,
+        duplicate: &ImageProperties,
+        keeper: &ImageProperties,
+    ) -> Result<(), io::Error> {
+        let destination = self.plan.destination(duplicate);
+        let journal = match &mut self.journal {
+            Some(journal) => journal,
+            None => {
+                return self
+                    .action
+                    .apply(duplicate, keeper, destination)
+                    .map(|_| ())
+            }
+        };
+
+        // Everything the journal needs from the duplicate has to be read before it's changed
+        let original = fs::canonicalize(&duplicate.path)?;
+        let checksum = journal::checksum(&duplicate.path)?;
+        let new = self
+            .action
+            .apply(duplicate, keeper, destination)?
+            .map(|new| fs::canonicalize(&new).unwrap_or(new));
+
+        let entry = JournalEntry {
+            action: self.action,
+            original,
+            new,
+            keeper: fs::canonicalize(&keeper.path).unwrap_or_else(|_| keeper.path.clone()),
+            checksum,
+        };
+        if let Err(err) = journal.record(&entry) {
+            self.journal_errors.push(err);
+        }
+        Ok(())
+    }
+}
+
+/// Which image each duplicate in `groups` is a duplicate of and how, for the duplicates that get
+/// acted on.
+///
+/// An image can be in more than one group: the keeper of a set of identical copies can be a
+/// duplicate in a perceptual group, and an image can be a crop of one image and the original of
+/// another. Its first group wins, and nothing is ever made a duplicate of an image that is (through
+/// any number of groups) a duplicate of it, so following the keepers always ends at an image that
+/// stays put.
+fn final_keepers(groups: &[DuplicateGroup]) -> Keepers<'_> {
+    let mut keepers = Keepers::new();
+    for group in groups {
+        for duplicate in group.duplicates() {
+            if keepers.contains_key(duplicate.path.as_path())
+                || resolve_keeper(&keepers, group.keeper(), |_| true).path == duplicate.path
+            {
+                continue;
+            }
+            // Another name for the kept file isn't a copy of it, acting on it would at best free
+            // nothing and at worst get rid of the only copy
+            if is_same_file(&duplicate.path, &group.keeper().path).unwrap_or(false) {
+                continue;
+            }
+            keepers.insert(&duplicate.path, (group.keeper(), group.kind));
+        }
+    }
+    keepers
+}
+
+type Keepers<'a> = HashMap<&'a Path, (&'a ImageProperties, MatchKind)>;
+
+/// The image that stays put in place of `image`, following `keepers` from one group to the next,
+/// but only through groups of the kinds `acted_on` says the action is applied to.
+fn resolve_keeper<'a, F>(
+    keepers: &Keepers<'a>,
+    mut image: &'a ImageProperties,
+    acted_on: F,
+) -> &'a ImageProperties
+where
+    F: Fn(MatchKind) -> bool,
+{
+    while let Some(&(keeper, kind)) = keepers.get(image.path.as_path()) {
+        if !acted_on(kind) {
+            break;
+        }
+        image = keeper;
+    }
+    image
+}
+
+/// The error for linking `duplicate` to a `keeper` it isn't a byte-identical copy of.
+fn not_a_copy(duplicate: &ImageProperties, keeper: &ImageProperties) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{:?} isn't an exact copy of {:?}, linking would lose it",
+            duplicate.path, keeper.path
+        ),
+    )
+}
+
+/// Moves `from` to `to`. Moves across filesystems copy the file, sync it to disk and check the copy
+/// matches before the original is deleted.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    match fs::rename(from, to) {
+        Err(e) if is_cross_device(&e) => {}
+        result => return result,
+    }
+
+    copy_new(from, to)?;
+    let verified = match (exact::content_hash(from), exact::content_hash(to)) {
+        (Ok(original), Ok(copy)) if original == copy => sync_parent(to),
+        (Ok(_), Ok(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("copy of {:?} at {:?} doesn't match it", from, to),
+        )),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    if let Err(e) = verified {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+/// Whether `e` came from trying to rename a file across filesystems.
+pub fn is_cross_device(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        e.raw_os_error() == Some(libc::EXDEV)
+    }
+    #[cfg(not(unix))]
+    {
+        // ERROR_NOT_SAME_DEVICE
+        e.raw_os_error() == Some(17)
+    }
+}
+
+/// Syncs the directory `path` is in, so a newly created `path` survives a crash.
+fn sync_parent(path: &Path) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Copies `from` to `to`, failing instead of overwriting if `to` already exists.
+fn copy_new(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let mut out = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    let copied = io::copy(&mut fs::File::open(from)?, &mut out).and_then(|_| out.sync_all());
+    if copied.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    copied
+}
+
+/// Replaces `path` with a link made by `link`, which is given a temporary path next to `path` to
+/// create it at. The link is then renamed over `path`, so `path` is never missing, even briefly.
+fn replace_with_link<F>(path: &Path, link: F) -> Result<(), io::Error>
+where
+    F: FnOnce(&Path) -> Result<(), io::Error>,
+{
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = name.to_os_string();
+    tmp_name.push(".image-dedup-link");
+    let tmp = path.with_file_name(tmp_name);
+
+    link(&tmp)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> Result<bool, io::Error> {
+    Ok(scan::file_id(a)? == scan::file_id(b)?)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> Result<(), io::Error> {
+    std::os::windows::fs::symlink_file(target, link)
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::hash::PerceptualHash;
+
+#[derive(Debug)]
+/// A single node of the tree, holding one hash and every value inserted under it.
+struct Node<T> {
+    hash: PerceptualHash,
+    values: Vec<T>,
+    /// Child node indices, keyed by their distance to this node's hash.
+    children: HashMap<u32, usize>,
+}
+
+#[derive(Debug)]
+/// A Burkhard-Keller tree over perceptual hashes, using the Hamming distance as its metric.
+///
+/// Lookups only descend into children whose edge distance is within `max_distance` of the
+/// query's distance to the current node (thanks to the triangle inequality), so finding near
+/// neighbours touches a small fraction of the tree instead of comparing against every hash.
+pub struct BkTree<T> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> BkTree<T> {
+    pub fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    /// Inserts `value` under `hash`. Values inserted with an identical hash share a node.
+    pub fn insert(&mut self, hash: PerceptualHash, value: T) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                hash,
+                values: vec![value],
+                children: HashMap::new(),
+            });
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = self.nodes[current].hash.distance(&hash);
+            if distance == 0 {
+                self.nodes[current].values.push(value);
+                return;
+            }
+
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let new_idx = self.nodes.len();
+                    self.nodes.push(Node {
+                        hash,
+                        values: vec![value],
+                        children: HashMap::new(),
+                    });
+                    self.nodes[current].children.insert(distance, new_idx);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns every value whose hash is at most `max_distance` bits away from `hash`.
+    pub fn find(&self, hash: &PerceptualHash, max_distance: u32) -> Vec<&T> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut to_visit = vec![0];
+        while let Some(idx) = to_visit.pop() {
+            let node = &self.nodes[idx];
+            let distance = node.hash.distance(hash);
+            if distance <= max_distance {
+                found.extend(node.values.iter());
+            }
+
+            let lower = distance.saturating_sub(max_distance);
+            let upper = distance.saturating_add(max_distance);
+            to_visit.extend(
+                node.children
+                    .iter()
+                    .filter(|(edge, _)| (lower..=upper).contains(*edge))
+                    .map(|(_, child)| *child),
+            );
+        }
+
+        found
+    }
+}
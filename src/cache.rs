@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::hash::{HashOptions, PerceptualHash};
+
+/// Identifies the cache file format, bump `VERSION` whenever the layout changes.
+const MAGIC: &[u8; 4] = b"IDDC";
+const VERSION: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Enough about a file to tell whether it changed since it was last hashed.
+pub struct FileStamp {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    inode: u64,
+}
+
+impl FileStamp {
+    /// Returns `None` if the platform doesn't report a usable modification time.
+    pub fn new(metadata: &Metadata) -> Option<FileStamp> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(FileStamp {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            inode,
+        })
+    }
+
+    /// Size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.mtime_secs, self.mtime_nanos)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Everything about an image that's expensive to work out, so it's only done once per file.
+pub struct CachedImage {
+    pub stamp: FileStamp,
+    pub width: u32,
+    pub height: u32,
+    pub hash: PerceptualHash,
+    /// See `ImageProperties::transformed`.
+    pub transformed: Vec<PerceptualHash>,
+    /// See `ImageProperties::crops`.
+    pub crops: Vec<PerceptualHash>,
+}
+
+#[derive(Debug)]
+/// An on-disk cache of image hashes, keyed by canonical path.
+///
+/// Entries are only valid while the file's size, modification time and inode match what they were
+/// when it was hashed. Entries that weren't used during a run are pruned on save if their file no
+/// longer matches.
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, CachedImage>,
+    /// Paths looked up or inserted during this run, these are known to be up to date.
+    used: HashSet<PathBuf>,
+}
+
+impl HashCache {
+    /// Where the cache for `options` lives: `$XDG_CACHE_HOME/image-dedup-cli`, falling back to
+    /// `~/.cache/image-dedup-cli`. Every set of hash options gets its own file, since their hashes
+    /// can't be compared anyway.
+    pub fn default_path(options: &HashOptions) -> Option<PathBuf> {
+        let rotations = if options.rotation_invariant {
+            "-rotations"
+        } else {
+            ""
+        };
+        let exif = if options.exif_orientation {
+            ""
+        } else {
+            "-no-exif"
+        };
+        let trimmed = if options.trim_borders { "-trimmed" } else { "" };
+        let crops = if options.detect_crops { "-crops" } else { "" };
+        let cache_home = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+
+        Some(cache_home.join("image-dedup-cli").join(format!(
+            "hashes-{}-{}-{}{}{}{}{}.bin",
+            options.algorithm, options.size, options.filter, rotations, exif, trimmed, crops
+        )))
+    }
+
+    /// An empty cache, to be written to `path` on save.
+    pub fn new(path: PathBuf) -> HashCache {
+        HashCache {
+            path,
+            entries: HashMap::new(),
+            used: HashSet::new(),
+        }
+    }
+
+    /// Loads the cache at `path`, a missing file makes an empty cache. Fails if the file can't be
+    /// read or isn't a cache, falling back to `new` overwrites it on save.
+    pub fn open(path: PathBuf) -> Result<HashCache, io::Error> {
+        let entries = match File::open(&path).and_then(|file| read_cache(&mut BufReader::new(file)))
+        {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(HashCache {
+            path,
+            entries,
+            used: HashSet::new(),
+        })
+    }
+
+    /// Returns the cached image at `path` if the file hasn't changed since it was hashed.
+    pub fn get(&mut self, path: &Path, stamp: FileStamp) -> Option<CachedImage> {
+        let cached = self.entries.get(path).filter(|c| c.stamp == stamp)?.clone();
+        self.used.insert(path.to_path_buf());
+        Some(cached)
+    }
+
+    pub fn insert(&mut self, path: PathBuf, image: CachedImage) {
+        self.used.insert(path.clone());
+        self.entries.insert(path, image);
+    }
+
+    /// Writes the cache back to disk, dropping entries whose files were removed or changed. See
+    /// `write_atomically` for how.
+    pub fn save(&self) -> Result<(), io::Error> {
+        let fresh: Vec<(&PathBuf, &CachedImage)> = self
+            .entries
+            .iter()
+            .filter(|(path, cached)| {
+                self.used.contains(*path)
+                    || fs::metadata(path)
+                        .ok()
+                        .and_then(|md| FileStamp::new(&md))
+                        .map(|stamp| stamp == cached.stamp)
+                        .unwrap_or(false)
+            })
+            .collect();
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&self.path, |out| {
+            out.write_all(MAGIC)?;
+            out.write_all(&VERSION.to_le_bytes())?;
+            write_entries(out, &fresh)
+        })
+    }
+}
+
+/// Writes the file at `path` with `write`, shared by the cache and the index.
+///
+/// It's written to a temporary file first and renamed over the old one, so an interrupted write
+/// never leaves a half-written file behind. The temporary file is named after the process, so a
+/// `watch` session and a scheduled run saving at the same time can't write into each other's.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(format!(".{}.tmp", process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = File::create(&tmp_path)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write(&mut out)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written
+}
+
+/// Writes `entries` in the format shared by the cache and the index, see `read_entries`.
+pub(crate) fn write_entries<W: Write>(
+    out: &mut W,
+    entries: &[(&PathBuf, &CachedImage)],
+) -> io::Result<()> {
+    // Paths that aren't valid UTF-8 are left out, they just get hashed every run
+    let entries: Vec<(&str, &CachedImage)> = entries
+        .iter()
+        .filter_map(|(path, cached)| path.to_str().map(|p| (p, *cached)))
+        .collect();
+    out.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (path, cached) in entries {
+        write_bytes(out, path.as_bytes())?;
+        out.write_all(&cached.stamp.size.to_le_bytes())?;
+        out.write_all(&cached.stamp.mtime_secs.to_le_bytes())?;
+        out.write_all(&cached.stamp.mtime_nanos.to_le_bytes())?;
+        out.write_all(&cached.stamp.inode.to_le_bytes())?;
+        out.write_all(&cached.width.to_le_bytes())?;
+        out.write_all(&cached.height.to_le_bytes())?;
+        write_bytes(out, cached.hash.as_bytes())?;
+        write_hashes(out, &cached.transformed)?;
+        write_hashes(out, &cached.crops)?;
+    }
+
+    Ok(())
+}
+
+fn read_cache<R: Read>(input: &mut R) -> io::Result<HashMap<PathBuf, CachedImage>> {
+    read_header(input, MAGIC, VERSION)?;
+    read_entries(input)
+}
+
+/// Checks that a file starts with `magic` and `version`, as every file written by this crate does.
+pub(crate) fn read_header<R: Read>(input: &mut R, magic: &[u8; 4], version: u32) -> io::Result<()> {
+    let mut found = [0; 4];
+    input.read_exact(&mut found)?;
+    if &found != magic || read_u32(input)? != version {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "not the expected kind of file, or one from a different version",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads entries written by `write_entries`: a count, then each path followed by its `CachedImage`.
+pub(crate) fn read_entries<R: Read>(input: &mut R) -> io::Result<HashMap<PathBuf, CachedImage>> {
+    let count = read_u64(input)?;
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let path = String::from_utf8(read_bytes(input)?)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        let stamp = FileStamp {
+            size: read_u64(input)?,
+            mtime_secs: read_u64(input)?,
+            mtime_nanos: read_u32(input)?,
+            inode: read_u64(input)?,
+        };
+        let width = read_u32(input)?;
+        let height = read_u32(input)?;
+        let hash = PerceptualHash::from(read_bytes(input)?.as_slice());
+        let transformed = read_hashes(input)?;
+        let crops = read_hashes(input)?;
+
+        entries.insert(
+            PathBuf::from(path),
+            CachedImage {
+                stamp,
+                width,
+                height,
+                hash,
+                transformed,
+                crops,
+            },
+        );
+    }
+
+    Ok(entries)
+}
+
+fn write_hashes<W: Write>(out: &mut W, hashes: &[PerceptualHash]) -> io::Result<()> {
+    out.write_all(&(hashes.len() as u32).to_le_bytes())?;
+    for hash in hashes {
+        write_bytes(out, hash.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_hashes<R: Read>(input: &mut R) -> io::Result<Vec<PerceptualHash>> {
+    (0..read_u32(input)?)
+        .map(|_| Ok(PerceptualHash::from(read_bytes(input)?.as_slice())))
+        .collect()
+}
+
+/// Writes a length-prefixed byte string.
+pub(crate) fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+pub(crate) fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(input)? as usize;
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
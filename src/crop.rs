@@ -0,0 +1,122 @@
+//! Telling when images only differ by a border, or one is a crop of the other.
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+/// How far apart (per channel) colours can be and still count as the same border colour, so
+/// JPEG noise in a black bar doesn't stop it from being trimmed.
+const BORDER_TOLERANCE: u8 = 24;
+
+/// Images smaller than this on either side don't count as a crop of anything, there's too little of
+/// them to tell what they were cut out of.
+pub const MIN_CROP_SIZE: u32 = 32;
+
+/// Fractions of an image's width and height that crop windows are made of.
+const WINDOW_SIZES: [f64; 4] = [0.5, 0.7, 0.85, 1.0];
+
+/// Cuts off any border of a uniform colour around `image`. The top and left borders are the
+/// colour of the top left corner, the bottom and right ones that of the bottom right corner.
+/// Images that are all border are returned as they are.
+pub fn trim_borders(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return image.clone();
+    }
+    let same = |a: Rgba<u8>, b: Rgba<u8>| {
+        a.0.iter()
+            .zip(b.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= BORDER_TOLERANCE)
+    };
+    let row_is = |y: u32, colour| (0..width).all(|x| same(image.get_pixel(x, y), colour));
+    let column_is =
+        |x: u32, top, bottom, colour| (top..bottom).all(|y| same(image.get_pixel(x, y), colour));
+
+    let top_left = image.get_pixel(0, 0);
+    let bottom_right = image.get_pixel(width - 1, height - 1);
+    let top = (0..height).find(|&y| !row_is(y, top_left));
+    let top = match top {
+        Some(top) => top,
+        None => return image.clone(),
+    };
+    let bottom = (top..height)
+        .rev()
+        .find(|&y| !row_is(y, bottom_right))
+        .map_or(top + 1, |y| y + 1);
+    let left = (0..width)
+        .find(|&x| !column_is(x, top, bottom, top_left))
+        .unwrap_or(0);
+    let right = (left..width)
+        .rev()
+        .find(|&x| !column_is(x, top, bottom, bottom_right))
+        .map_or(left + 1, |x| x + 1);
+
+    if (left, top, right, bottom) == (0, 0, width, height) {
+        return image.clone();
+    }
+    image.crop_imm(left, top, right - left, bottom - top)
+}
+
+/// Whether an image of this size is big enough to be a crop, see `MIN_CROP_SIZE`.
+pub fn is_big_enough(width: u32, height: u32) -> bool {
+    width >= MIN_CROP_SIZE && height >= MIN_CROP_SIZE
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A part of an image, in fractions of its width and height so it applies to any size.
+pub struct Window {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Window {
+    /// How much of the image the window covers, from 0 to 1.
+    pub fn area(&self) -> f64 {
+        self.width * self.height
+    }
+
+    pub fn crop(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+        let (width, height) = (width as f64, height as f64);
+        image.crop_imm(
+            (self.x * width) as u32,
+            (self.y * height) as u32,
+            ((self.width * width) as u32).max(1),
+            ((self.height * height) as u32).max(1),
+        )
+    }
+}
+
+/// Every window crops are looked for in: each combination of `WINDOW_SIZES` for the width and
+/// height except the whole image, placed against each side and in the middle. Crop hashes are
+/// kept in this order.
+pub fn windows() -> Vec<Window> {
+    let mut windows = Vec::new();
+    for &width in &WINDOW_SIZES {
+        for &height in &WINDOW_SIZES {
+            if width == 1.0 && height == 1.0 {
+                continue;
+            }
+            for x in offsets(width) {
+                for &y in &offsets(height) {
+                    windows.push(Window {
+                        x,
+                        y,
+                        width,
+                        height,
+                    });
+                }
+            }
+        }
+    }
+    windows
+}
+
+/// Where a window `size` wide can start: against either side or in the middle.
+fn offsets(size: f64) -> Vec<f64> {
+    if size == 1.0 {
+        vec![0.0]
+    } else {
+        vec![0.0, (1.0 - size) / 2.0, 1.0 - size]
+    }
+}
@@ -0,0 +1,64 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use glob::PatternError;
+use image::ImageError;
+
+#[derive(Debug)]
+/// Everything that can go wrong finding duplicates or acting on them, with enough context to tell
+/// the user which file or pattern it was about.
+pub enum Error {
+    /// An `--include` or `--exclude` pattern that isn't a valid glob.
+    Pattern {
+        pattern: String,
+        source: PatternError,
+    },
+    /// A file or directory that couldn't be read.
+    Io { path: PathBuf, source: io::Error },
+    /// A file that looked like an image, but couldn't be decoded as one.
+    Decode { path: PathBuf, source: ImageError },
+    /// An action that failed on a duplicate, `description` being what it tried to do.
+    Action {
+        description: String,
+        source: io::Error,
+    },
+}
+
+impl Error {
+    /// The error for failing to open the image at `path`, telling unreadable files apart from
+    /// undecodable ones.
+    pub fn opening(path: PathBuf, source: ImageError) -> Error {
+        match source {
+            ImageError::IoError(source) => Error::Io { path, source },
+            source => Error::Decode { path, source },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Pattern { pattern, source } => {
+                write!(f, "Invalid glob pattern {:?}: {}", pattern, source)
+            }
+            Error::Io { path, source } => write!(f, "Couldn't read {:?}: {}", path, source),
+            Error::Decode { path, source } => write!(f, "Couldn't decode {:?}: {}", path, source),
+            Error::Action {
+                description,
+                source,
+            } => write!(f, "Failed to {}: {}", description, source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Pattern { source, .. } => Some(source),
+            Error::Io { source, .. } | Error::Action { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+        }
+    }
+}
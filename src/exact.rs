@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use rayon::prelude::*;
+
+/// Splits `files` into sets of byte-identical files, and the files that have no identical copy.
+///
+/// Files are bucketed by size first, so only files that share their size with another file are
+/// ever read. Those are then compared by their BLAKE3 hash. Files that can't be read are treated
+/// as having no identical copy. Every set is sorted by path.
+pub fn split_identical<T, F>(files: Vec<T>, path: F) -> (Vec<Vec<T>>, Vec<T>)
+where
+    T: Send + Sync,
+    F: Fn(&T) -> &Path + Sync,
+{
+    let mut unique = Vec::new();
+    let mut by_size: HashMap<u64, Vec<T>> = HashMap::new();
+    for file in files {
+        match fs::metadata(path(&file)) {
+            Ok(md) => by_size.entry(md.len()).or_default().push(file),
+            Err(_) => unique.push(file),
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for (size, bucket) in by_size {
+        if bucket.len() == 1 {
+            unique.extend(bucket);
+        } else {
+            candidates.extend(bucket.into_iter().map(|file| (size, file)));
+        }
+    }
+
+    let digests: Vec<Option<blake3::Hash>> = candidates
+        .par_iter()
+        .map(|(_, file)| content_hash(path(file)).ok())
+        .collect();
+
+    let mut by_content: HashMap<(u64, blake3::Hash), Vec<T>> = HashMap::new();
+    for ((size, file), digest) in candidates.into_iter().zip(digests) {
+        match digest {
+            Some(digest) => by_content.entry((size, digest)).or_default().push(file),
+            None => unique.push(file),
+        }
+    }
+
+    let mut identical = Vec::new();
+    for (_, mut set) in by_content {
+        if set.len() == 1 {
+            unique.extend(set);
+        } else {
+            set.sort_by(|a, b| path(a).cmp(path(b)));
+            identical.push(set);
+        }
+    }
+
+    (identical, unique)
+}
+
+/// BLAKE3 hash of everything in the file at `path`.
+pub fn content_hash(path: &Path) -> Result<blake3::Hash, io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Whether the files at `a` and `b` are byte for byte the same, files that can't be read aren't.
+pub fn is_identical(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_md), Ok(b_md)) if a_md.len() == b_md.len() => {
+            matches!((content_hash(a), content_hash(b)), (Ok(a), Ok(b)) if a == b)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use img_hash::{HashAlg, Hasher, HasherConfig};
+
+use crate::crop;
+use crate::transform::Transform;
+
+/// Longest side images are shrunk to before being hashed in every orientation or crop window, so
+/// that doesn't take much longer than hashing them once.
+const TRANSFORM_SIZE: u32 = 256;
+
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+/// The perceptual hash of an image, which can be any number of bytes long depending on the hash
+/// size and algorithm it was made with.
+pub struct PerceptualHash(Box<[u8]>);
+
+impl PerceptualHash {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Number of differing bits between two hashes. Bytes past the end of the shorter hash count
+    /// as entirely different, though hashes made with the same options are always the same length.
+    pub fn distance(&self, other: &PerceptualHash) -> u32 {
+        let common: u32 = self
+            .0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        let extra = self.0.len().abs_diff(other.0.len()) as u32 * 8;
+        common + extra
+    }
+}
+
+impl From<&[u8]> for PerceptualHash {
+    fn from(bytes: &[u8]) -> Self {
+        PerceptualHash(bytes.into())
+    }
+}
+
+impl fmt::Display for PerceptualHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The perceptual hashing algorithms images can be compared with.
+pub enum HashAlgorithm {
+    Mean,
+    Gradient,
+    VertGradient,
+    DoubleGradient,
+    Blockhash,
+    /// Mean hash over the DCT of the image, better known as pHash.
+    Dct,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(HashAlgorithm::Mean),
+            "gradient" => Ok(HashAlgorithm::Gradient),
+            "vert-gradient" => Ok(HashAlgorithm::VertGradient),
+            "double-gradient" => Ok(HashAlgorithm::DoubleGradient),
+            "blockhash" => Ok(HashAlgorithm::Blockhash),
+            "dct" => Ok(HashAlgorithm::Dct),
+            _ => Err(format!(
+                "unknown hash algorithm {:?}, expected one of: mean, gradient, vert-gradient, double-gradient, blockhash, dct",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Mean => "mean",
+            HashAlgorithm::Gradient => "gradient",
+            HashAlgorithm::VertGradient => "vert-gradient",
+            HashAlgorithm::DoubleGradient => "double-gradient",
+            HashAlgorithm::Blockhash => "blockhash",
+            HashAlgorithm::Dct => "dct",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Filters images can be resized with before hashing, see `image::imageops::FilterType`.
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(&self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" => Ok(ResizeFilter::Triangle),
+            "catmull-rom" => Ok(ResizeFilter::CatmullRom),
+            "gaussian" => Ok(ResizeFilter::Gaussian),
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(format!(
+                "unknown resize filter {:?}, expected one of: nearest, triangle, catmull-rom, gaussian, lanczos3",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmull-rom",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Everything that affects the hash an image ends up with.
+pub struct HashOptions {
+    pub algorithm: HashAlgorithm,
+    /// Width and height of the hash in bits, so a size of 8 makes a 64-bit hash.
+    pub size: u32,
+    pub filter: ResizeFilter,
+    /// Also hash every rotated and mirrored version of images, so they match in any orientation.
+    pub rotation_invariant: bool,
+    /// Turn images upright according to their EXIF orientation before hashing them, so a camera
+    /// original matches a copy that was rotated for real.
+    pub exif_orientation: bool,
+    /// Cut uniform borders off images before hashing them, so letterboxed or framed copies match.
+    pub trim_borders: bool,
+    /// Also hash windows of every image, so crops of it can be found.
+    pub detect_crops: bool,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        HashOptions {
+            algorithm: HashAlgorithm::Gradient,
+            size: 8,
+            filter: ResizeFilter::Triangle,
+            rotation_invariant: false,
+            exif_orientation: true,
+            trim_borders: false,
+            detect_crops: false,
+        }
+    }
+}
+
+impl HashOptions {
+    pub fn hasher(&self) -> ImageHasher {
+        let (alg, dct) = match self.algorithm {
+            HashAlgorithm::Mean => (HashAlg::Mean, false),
+            HashAlgorithm::Gradient => (HashAlg::Gradient, false),
+            HashAlgorithm::VertGradient => (HashAlg::VertGradient, false),
+            HashAlgorithm::DoubleGradient => (HashAlg::DoubleGradient, false),
+            HashAlgorithm::Blockhash => (HashAlg::Blockhash, false),
+            HashAlgorithm::Dct => (HashAlg::Mean, true),
+        };
+
+        let mut config = HasherConfig::new()
+            .hash_alg(alg)
+            .hash_size(self.size, self.size)
+            .resize_filter(self.filter.filter_type());
+        if dct {
+            config = config.preproc_dct();
+        }
+
+        ImageHasher {
+            hasher: config.to_hasher(),
+            options: *self,
+        }
+    }
+}
+
+/// Hashes images according to a set of `HashOptions`.
+pub struct ImageHasher {
+    hasher: Hasher,
+    options: HashOptions,
+}
+
+impl ImageHasher {
+    pub fn options(&self) -> &HashOptions {
+        &self.options
+    }
+
+    pub fn hash(&self, image: &DynamicImage) -> PerceptualHash {
+        PerceptualHash::from(self.hasher.hash_image(image).as_bytes())
+    }
+
+    /// The hash of `image`, along with the hashes of its other orientations in `Transform::ALL`
+    /// order if the options are rotation invariant.
+    ///
+    /// Rotation invariant hashes are made from a shrunk copy of the image, so they don't match
+    /// the hashes of the same image without rotation invariance.
+    pub fn hash_transforms(&self, image: &DynamicImage) -> (PerceptualHash, Vec<PerceptualHash>) {
+        if !self.options.rotation_invariant {
+            return (self.hash(image), Vec::new());
+        }
+
+        let shrunk = self.shrink(image);
+        let transformed = Transform::ALL[1..]
+            .iter()
+            .map(|transform| self.hash(&transform.apply(&shrunk)))
+            .collect();
+        (self.hash(&shrunk), transformed)
+    }
+
+    /// Hashes of every `crop::windows()` of `image`, in that order, if the options detect crops.
+    pub fn hash_crops(&self, image: &DynamicImage) -> Vec<PerceptualHash> {
+        if !self.options.detect_crops {
+            return Vec::new();
+        }
+        let shrunk = self.shrink(image);
+        crop::windows()
+            .iter()
+            .map(|window| self.hash(&window.crop(&shrunk)))
+            .collect()
+    }
+
+    fn shrink(&self, image: &DynamicImage) -> DynamicImage {
+        if image.width().max(image.height()) > TRANSFORM_SIZE {
+            image.resize(
+                TRANSFORM_SIZE,
+                TRANSFORM_SIZE,
+                self.options.filter.filter_type(),
+            )
+        } else {
+            image.clone()
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! A static HTML page of every duplicate group, for reviewing results in a browser.
+//!
+//! The page is entirely self-contained: thumbnails are embedded as data URIs and the styling is
+//! inline, so it can be mailed around or opened anywhere without the images it describes.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use image::{DynamicImage, ImageOutputFormat};
+use rayon::prelude::*;
+
+use image_dedup_cli::{
+    open_upright, Action, DuplicateGroup, HashOptions, ImageProperties, MatchKind, Plan, Transform,
+};
+
+use crate::review::human_size;
+
+/// Thumbnails fit in a square this many pixels wide.
+const THUMBNAIL_SIZE: u32 = 240;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; background: #f4f4f4; color: #222; }
+h1 { margin-bottom: 0.2em; }
+section { background: #fff; border-radius: 6px; padding: 1em 1.5em; margin: 1.5em 0; }
+h2 { font-size: 1.1em; margin: 0 0 1em; }
+h2 code { font-weight: normal; color: #777; }
+.images { display: flex; flex-wrap: wrap; gap: 1em; }
+.image { width: 260px; border: 3px solid #e0a030; border-radius: 6px; padding: 8px; }
+.image.keep { border-color: #40a040; }
+.thumb { width: 240px; height: 240px; display: flex; align-items: center; justify-content: center; background: #eee; }
+.thumb img { max-width: 240px; max-height: 240px; }
+.path { font-family: monospace; word-break: break-all; margin: 0.5em 0; }
+.meta { color: #555; font-size: 0.9em; margin: 0.2em 0; }
+.action { font-weight: bold; margin-top: 0.5em; }
+";
+
+/// Writes the HTML report for `groups` to `out`, showing what `action` does (or would do) to every
+/// duplicate and where `plan` puts it.
+///
+/// Every image is decoded again for its thumbnail, in parallel on the rayon thread pool, and turned
+/// upright if `hash` turns images upright for hashing. Images that can't be decoded anymore just go
+/// without one.
+pub fn write_html_report<W: Write>(
+    mut out: W,
+    groups: &[DuplicateGroup],
+    action: Action,
+    plan: &Plan,
+    hash: &HashOptions,
+) -> Result<(), io::Error> {
+    // Keepers of identical copies can show up in two groups, no need to decode them twice
+    let mut paths: Vec<&Path> = groups
+        .iter()
+        .flat_map(|group| group.images.iter().map(|img| img.path.as_path()))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    let thumbnails: HashMap<&Path, Option<String>> = paths
+        .into_par_iter()
+        .map(|path| (path, thumbnail(path, hash)))
+        .collect();
+
+    let duplicates: usize = groups.iter().map(|group| group.images.len() - 1).sum();
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>Duplicate images</title>")?;
+    writeln!(out, "<style>{}</style>\n</head>\n<body>", STYLE)?;
+    writeln!(out, "<h1>Duplicate images</h1>")?;
+    writeln!(
+        out,
+        "<p>{} groups, {} duplicates. Kept images have a green border, everything in orange gets \
+         the action listed under it.</p>",
+        groups.len(),
+        duplicates
+    )?;
+
+    for (idx, group) in groups.iter().enumerate() {
+        writeln!(out, "<section>")?;
+        writeln!(
+            out,
+            "<h2>Group {} &middot; {} match <code>{}</code></h2>",
+            idx,
+            group.kind,
+            group.keeper().hash
+        )?;
+        writeln!(out, "<div class=\"images\">")?;
+        for (img_idx, img) in group.images.iter().enumerate() {
+            let keep = img_idx == group.keeper;
+            writeln!(
+                out,
+                "<div class=\"image{}\">",
+                if keep { " keep" } else { "" }
+            )?;
+            match thumbnails.get(img.path.as_path()).and_then(Option::as_ref) {
+                Some(uri) => writeln!(
+                    out,
+                    "<div class=\"thumb\"><img src=\"{}\" alt=\"\"></div>",
+                    uri
+                )?,
+                None => writeln!(out, "<div class=\"thumb\">No preview</div>")?,
+            }
+            writeln!(
+                out,
+                "<div class=\"path\">{}</div>",
+                escape(&img.path.to_string_lossy())
+            )?;
+            writeln!(
+                out,
+                "<div class=\"meta\">{}, {}&times;{}</div>",
+                human_size(img.file_size),
+                img.width,
+                img.height
+            )?;
+            writeln!(out, "<div class=\"meta\">{}</div>", similarity(group, img))?;
+            let planned = if keep {
+                "Kept".to_string()
+            } else {
+                capitalize(&action.describe(img, group.keeper(), plan.destination(img)))
+            };
+            writeln!(out, "<div class=\"action\">{}</div>", escape(&planned))?;
+            writeln!(out, "</div>")?;
+        }
+        writeln!(out, "</div>\n</section>")?;
+    }
+
+    writeln!(out, "</body>\n</html>")?;
+    out.flush()
+}
+
+/// The image at `path` shrunk down to a JPEG thumbnail, as a data URI. It's turned the same way
+/// as it was for hashing, so it matches the width and height next to it.
+fn thumbnail(path: &Path, hash: &HashOptions) -> Option<String> {
+    let thumb = open_upright(path, hash)
+        .ok()?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8();
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(thumb)
+        .write_to(&mut jpeg, ImageOutputFormat::Jpeg(80))
+        .ok()?;
+    Some(format!("data:image/jpeg;base64,{}", base64::encode(&jpeg)))
+}
+
+/// How similar `img` is to the kept image of `group`.
+fn similarity(group: &DuplicateGroup, img: &ImageProperties) -> String {
+    if img.path == group.keeper().path {
+        return "Everything else is compared to this image".to_string();
+    }
+    if group.kind == MatchKind::Exact {
+        return "Identical file".to_string();
+    }
+    let bits = group.keeper().hash.as_bytes().len() * 8;
+    let (transform, distance) = group.relation(img);
+    let similarity = format!(
+        "{:.0}% similar ({} of {} hash bits differ)",
+        100.0 * (1.0 - distance as f64 / bits as f64),
+        distance,
+        bits
+    );
+    if let Some(overlap) = group.overlap() {
+        return format!(
+            "Crop covering about {:.0}% of the other image, {}",
+            overlap * 100.0,
+            similarity
+        );
+    }
+    match transform {
+        Transform::Identity => similarity,
+        transform => format!("{} after {}", similarity, transform),
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Escapes everything that means something in HTML text and attributes.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
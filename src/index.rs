@@ -0,0 +1,339 @@
+//! A collection's hashes kept on disk, so similar images can be looked up without scanning it.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use glob::Pattern;
+
+use crate::cache::{self, CachedImage, FileStamp};
+use crate::{
+    action, crop, hash_images, open_for_hashing, Error, HashCache, HashOptions, ImageProperties,
+    PerceptualHash, Progress, Scanner,
+};
+
+/// Identifies the index file format, bump `VERSION` whenever the layout changes.
+const MAGIC: &[u8; 4] = b"IDDX";
+const VERSION: u32 = 4;
+
+#[derive(Debug)]
+/// The hashes of every image in a collection, along with the options they were made with.
+///
+/// The scanner and hash options are stored in the index file, so every update looks at the same
+/// files the same way the index was built, and queries are hashed to match. Entries are keyed by
+/// absolute path and stamped like `HashCache` entries, so an update only hashes what changed.
+pub struct Index {
+    path: PathBuf,
+    root: PathBuf,
+    scanner: Scanner,
+    options: HashOptions,
+    entries: HashMap<PathBuf, CachedImage>,
+}
+
+#[derive(Debug, Default)]
+/// What an `Index::update` changed.
+pub struct IndexUpdate {
+    /// Images that weren't in the index yet.
+    pub added: usize,
+    /// Images that were modified since they were last indexed, and hashed again.
+    pub changed: usize,
+    /// Images that are gone, or that can't be read anymore.
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Every file or directory that had to be skipped, and why.
+    pub skipped: Vec<Error>,
+}
+
+impl Index {
+    /// An empty index of the collection at `root`, to be written to `path`. Nothing is read or
+    /// written until `update` and `save`.
+    pub fn new(
+        path: PathBuf,
+        root: &Path,
+        scanner: Scanner,
+        options: HashOptions,
+    ) -> Result<Index, Error> {
+        let root = fs::canonicalize(root).map_err(|source| Error::Io {
+            path: root.to_path_buf(),
+            source,
+        })?;
+        Ok(Index {
+            path,
+            root,
+            scanner,
+            options,
+            entries: HashMap::new(),
+        })
+    }
+
+    /// Loads the index at `path`.
+    pub fn open(path: PathBuf) -> io::Result<Index> {
+        let mut input = BufReader::new(File::open(&path)?);
+        cache::read_header(&mut input, MAGIC, VERSION)?;
+
+        let root = PathBuf::from(read_string(&mut input)?);
+        let options = HashOptions {
+            algorithm: parse(&read_string(&mut input)?)?,
+            size: cache::read_u32(&mut input)?,
+            filter: parse(&read_string(&mut input)?)?,
+            rotation_invariant: cache::read_u32(&mut input)? != 0,
+            exif_orientation: cache::read_u32(&mut input)? != 0,
+            trim_borders: cache::read_u32(&mut input)? != 0,
+            detect_crops: cache::read_u32(&mut input)? != 0,
+        };
+        let scanner = Scanner {
+            recursive: cache::read_u32(&mut input)? != 0,
+            include: read_patterns(&mut input)?,
+            exclude: read_patterns(&mut input)?,
+            formats: parse(&read_string(&mut input)?)?,
+        };
+        let entries = cache::read_entries(&mut input)?;
+
+        Ok(Index {
+            path,
+            root,
+            scanner,
+            options,
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The collection this is an index of.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn options(&self) -> &HashOptions {
+        &self.options
+    }
+
+    /// How many images are in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Brings the index up to date with the collection: new and modified images are hashed, and
+    /// images that are gone are dropped. Building an index is just updating an empty one.
+    ///
+    /// Hashes are looked up in and added to `cache` if there is one, which has to be the cache for
+    /// this index's hash options. Fails without changing anything if the collection itself can't
+    /// be read, rather than emptying the index.
+    pub fn update(
+        &mut self,
+        cache: Option<&Mutex<HashCache>>,
+        progress: &dyn Progress,
+    ) -> Result<IndexUpdate, Error> {
+        if let Err(source) = fs::read_dir(&self.root) {
+            return Err(Error::Io {
+                path: self.root.clone(),
+                source,
+            });
+        }
+
+        let mut update = IndexUpdate::default();
+        let (found, errors) = self.scanner.find_images(&self.root);
+        update.skipped.extend(errors);
+        progress.found(found.len());
+
+        let mut stale = Vec::new();
+        let mut stamps = Vec::new();
+        let mut seen = HashSet::with_capacity(found.len());
+        for path in found {
+            let stamp = match fs::metadata(&path).map(|md| FileStamp::new(&md)) {
+                Ok(Some(stamp)) => stamp,
+                // Every supported platform has modification times, without one there'd be no
+                // telling whether the image changed
+                Ok(None) => {
+                    update.skipped.push(Error::Io {
+                        path,
+                        source: io::Error::other("no modification time"),
+                    });
+                    continue;
+                }
+                Err(source) => {
+                    update.skipped.push(Error::Io { path, source });
+                    continue;
+                }
+            };
+            seen.insert(path.clone());
+            match self.entries.get(&path) {
+                Some(entry) if entry.stamp == stamp => {
+                    progress.hashed(&path, true);
+                    update.unchanged += 1;
+                }
+                _ => {
+                    stale.push((path, self.root.as_path()));
+                    stamps.push(stamp);
+                }
+            }
+        }
+
+        let hashed = hash_images(stale, &self.options, cache, progress);
+        for (hashed, stamp) in hashed.into_iter().zip(stamps) {
+            match hashed {
+                Ok(img) => {
+                    let previous = self.entries.insert(
+                        img.path,
+                        CachedImage {
+                            stamp,
+                            width: img.width,
+                            height: img.height,
+                            hash: img.hash,
+                            transformed: img.transformed,
+                            crops: img.crops,
+                        },
+                    );
+                    match previous {
+                        Some(_) => update.changed += 1,
+                        None => update.added += 1,
+                    }
+                }
+                // Whatever was indexed for it is out of date now
+                Err(e) => {
+                    if let Error::Io { path, .. } | Error::Decode { path, .. } = &e {
+                        seen.remove(path);
+                    }
+                    update.skipped.push(e);
+                }
+            }
+        }
+
+        let before = self.entries.len();
+        self.entries.retain(|path, _| seen.contains(path));
+        update.removed = before - self.entries.len();
+        Ok(update)
+    }
+
+    /// Every indexed image whose hash is at most `threshold` bits from the image at `image`, closest
+    /// first. The image itself is left out if it's in the index. With crops detected, images that
+    /// are a crop of it or that it's a crop of match as well.
+    ///
+    /// The index isn't checked against the collection, so images changed or removed since the
+    /// last update can still turn up.
+    pub fn query(&self, image: &Path, threshold: u32) -> Result<Vec<(&Path, u32)>, Error> {
+        let hasher = self.options.hasher();
+        let (decoded, (width, height)) =
+            open_for_hashing(image, &hasher).map_err(|e| Error::opening(image.to_path_buf(), e))?;
+        let (hash, transformed) = hasher.hash_transforms(&decoded);
+        let crops = hasher.hash_crops(&decoded);
+        let hashes: Vec<PerceptualHash> = std::iter::once(hash).chain(transformed).collect();
+
+        let mut matches: Vec<(&Path, u32)> = self
+            .entries
+            .iter()
+            .map(|(path, entry)| {
+                // Only the query is turned around, that's enough to match any orientation. Crops
+                // go both ways, either image can be a crop of the other
+                let query_crops = crop::is_big_enough(width, height);
+                let entry_crops = crop::is_big_enough(entry.width, entry.height);
+                let distance = hashes
+                    .iter()
+                    .map(|hash| entry.hash.distance(hash))
+                    .chain(
+                        entry
+                            .crops
+                            .iter()
+                            .filter(|_| query_crops)
+                            .map(|crop| crop.distance(&hashes[0])),
+                    )
+                    .chain(
+                        crops
+                            .iter()
+                            .filter(|_| entry_crops)
+                            .map(|crop| crop.distance(&entry.hash)),
+                    )
+                    .min();
+                (path.as_path(), distance.unwrap_or(u32::MAX))
+            })
+            .filter(|(path, distance)| {
+                *distance <= threshold && !action::is_same_file(path, image).unwrap_or(false)
+            })
+            .collect();
+        matches.sort_by_key(|(path, distance)| (*distance, *path));
+        Ok(matches)
+    }
+
+    /// Every indexed image, as it was when it was last hashed.
+    pub fn images(&self) -> Vec<ImageProperties> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| ImageProperties {
+                path: path.clone(),
+                root: self.root.clone(),
+                file_size: entry.stamp.size(),
+                modified: Some(entry.stamp.modified()),
+                width: entry.width,
+                height: entry.height,
+                hash: entry.hash.clone(),
+                transformed: entry.transformed.clone(),
+                crops: entry.crops.clone(),
+            })
+            .collect()
+    }
+
+    /// Writes the index to its path, see `cache::write_atomically` for how.
+    pub fn save(&self) -> io::Result<()> {
+        let root = self.root.to_str().ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "collection path isn't valid UTF-8")
+        })?;
+        let mut entries: Vec<(&PathBuf, &CachedImage)> = self.entries.iter().collect();
+        entries.sort_by_key(|(path, _)| *path);
+
+        cache::write_atomically(&self.path, |out| {
+            out.write_all(MAGIC)?;
+            out.write_all(&VERSION.to_le_bytes())?;
+            cache::write_bytes(out, root.as_bytes())?;
+            cache::write_bytes(out, self.options.algorithm.to_string().as_bytes())?;
+            out.write_all(&self.options.size.to_le_bytes())?;
+            cache::write_bytes(out, self.options.filter.to_string().as_bytes())?;
+            out.write_all(&(self.options.rotation_invariant as u32).to_le_bytes())?;
+            out.write_all(&(self.options.exif_orientation as u32).to_le_bytes())?;
+            out.write_all(&(self.options.trim_borders as u32).to_le_bytes())?;
+            out.write_all(&(self.options.detect_crops as u32).to_le_bytes())?;
+            out.write_all(&(self.scanner.recursive as u32).to_le_bytes())?;
+            write_patterns(out, &self.scanner.include)?;
+            write_patterns(out, &self.scanner.exclude)?;
+            cache::write_bytes(out, self.scanner.formats.to_string().as_bytes())?;
+            cache::write_entries(out, &entries)
+        })
+    }
+}
+
+fn read_string<R: Read>(input: &mut R) -> io::Result<String> {
+    String::from_utf8(cache::read_bytes(input)?)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Parses a setting stored with its `Display` form.
+fn parse<T: FromStr<Err = String>>(s: &str) -> io::Result<T> {
+    s.parse()
+        .map_err(|e: String| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write_patterns<W: Write>(out: &mut W, patterns: &[Pattern]) -> io::Result<()> {
+    out.write_all(&(patterns.len() as u32).to_le_bytes())?;
+    for pattern in patterns {
+        cache::write_bytes(out, pattern.as_str().as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_patterns<R: Read>(input: &mut R) -> io::Result<Vec<Pattern>> {
+    (0..cache::read_u32(input)?)
+        .map(|_| {
+            Pattern::new(&read_string(input)?)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
@@ -0,0 +1,245 @@
+//! A record of every change made to the filesystem, so a run can be undone later.
+//!
+//! Journals are JSON lines, one `JournalEntry` per changed file, in the order the changes were made.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::action::{self, Action};
+use crate::layout::numbered_name;
+use crate::{exact, trash};
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A single change made to a duplicate.
+pub struct JournalEntry {
+    pub action: Action,
+    /// Where the duplicate was before anything happened to it.
+    pub original: PathBuf,
+    /// Where the duplicate (or a copy of it) ended up, if it ended up anywhere new.
+    pub new: Option<PathBuf>,
+    /// The image the duplicate was a duplicate of.
+    pub keeper: PathBuf,
+    /// BLAKE3 hash of the duplicate's contents before the change.
+    pub checksum: String,
+}
+
+#[derive(Debug)]
+/// A journal being written, the file is only created once the first change is recorded.
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+    /// Whether the journal has to be a new file, rather than adding to one that's already there.
+    fresh: bool,
+}
+
+impl Journal {
+    /// Where journals go unless `--journal` says otherwise: a file named after the current time in
+    /// `$XDG_DATA_HOME/image-dedup-cli/journals`, falling back to `~/.local/share`. Runs started
+    /// in the same second get the same path, see `Journal::new_file` for keeping them apart.
+    pub fn default_path() -> Option<PathBuf> {
+        let data_home = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("share"),
+        };
+
+        Some(
+            data_home
+                .join("image-dedup-cli")
+                .join("journals")
+                .join(format!(
+                    "{}.jsonl",
+                    Local::now().format("%Y-%m-%dT%H-%M-%S")
+                )),
+        )
+    }
+
+    /// A journal at `path`, adding to whatever is already in it.
+    pub fn new(path: PathBuf) -> Journal {
+        Journal {
+            path,
+            file: None,
+            fresh: false,
+        }
+    }
+
+    /// A journal in a file of its own: `path` if nothing is there yet when the first change is
+    /// recorded, otherwise the first free numbered name next to it (`2021-11-02T18-30-00.2.jsonl`).
+    pub fn new_file(path: PathBuf) -> Journal {
+        Journal {
+            path,
+            file: None,
+            fresh: true,
+        }
+    }
+
+    /// Where the journal is, which for `new_file` journals is only settled once something was
+    /// recorded.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether anything was recorded in this journal.
+    pub fn is_empty(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Appends `entry` to the journal, syncing it to disk straight away so it survives a crash.
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), io::Error> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = match self.fresh {
+                    true => self.create_new()?,
+                    false => OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&self.path)?,
+                };
+                self.file.insert(file)
+            }
+        };
+
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Creates the first free one of the journal's path and its numbered names, moving the
+    /// journal there. Creating it is what claims a name, so two runs can never end up with the
+    /// same one.
+    fn create_new(&mut self) -> Result<File, io::Error> {
+        let name = self
+            .path
+            .file_name()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        for number in 1.. {
+            let path = match number {
+                1 => self.path.clone(),
+                _ => self.path.with_file_name(numbered_name(&name, number)),
+            };
+            match OpenOptions::new().append(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    self.path = path;
+                    return Ok(file);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("ran out of numbered names")
+    }
+}
+
+/// Hex BLAKE3 hash of the file at `path`, as stored in journal entries.
+pub fn checksum(path: &Path) -> Result<String, io::Error> {
+    exact::content_hash(path).map(|hash| hash.to_hex().to_string())
+}
+
+/// Reads every entry of the journal at `path`.
+pub fn read_journal(path: &Path) -> Result<Vec<JournalEntry>, io::Error> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[derive(Debug)]
+/// A change from a journal, and how undoing it went.
+pub struct Undone {
+    pub entry: JournalEntry,
+    pub result: Result<(), io::Error>,
+}
+
+/// Undoes every change in the journal at `path`, newest first, returning each change along with
+/// whether it was undone. Changes that can't be undone are skipped, the rest are still tried.
+pub fn undo(path: &Path) -> Result<Vec<Undone>, io::Error> {
+    let mut entries = read_journal(path)?;
+    entries.reverse();
+    Ok(entries
+        .into_iter()
+        .map(|entry| Undone {
+            result: undo_entry(&entry),
+            entry,
+        })
+        .collect())
+}
+
+/// Undoes a single change, refusing to touch anything that changed since.
+fn undo_entry(entry: &JournalEntry) -> Result<(), io::Error> {
+    fn conflict<T>(msg: String) -> Result<T, io::Error> {
+        Err(io::Error::other(msg))
+    }
+    let unchanged = |path: &Path| -> Result<bool, io::Error> {
+        match checksum(path) {
+            Ok(sum) => Ok(sum == entry.checksum),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                conflict(format!("{:?} no longer exists", path))
+            }
+            Err(e) => Err(e),
+        }
+    };
+
+    match entry.action {
+        Action::Move | Action::Trash => {
+            let new = entry.new.as_deref().ok_or(ErrorKind::InvalidData)?;
+            if entry.original.symlink_metadata().is_ok() {
+                return conflict(format!("{:?} is in the way", entry.original));
+            }
+            if !unchanged(new)? {
+                return conflict(format!("{:?} changed since it was moved", new));
+            }
+            if let Some(parent) = entry.original.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if entry.action == Action::Trash {
+                trash::restore(new, &entry.original)
+            } else {
+                action::move_file(new, &entry.original)
+            }
+        }
+        Action::Copy => {
+            let new = entry.new.as_deref().ok_or(ErrorKind::InvalidData)?;
+            if !unchanged(new)? {
+                return conflict(format!("{:?} changed since it was copied", new));
+            }
+            fs::remove_file(new)
+        }
+        Action::Hardlink | Action::Symlink => {
+            // The original contents survive if the link still leads to them (a hard link whose
+            // keeper was replaced later on), or if the keeper was an exact copy of them
+            let source = if unchanged(&entry.original)? {
+                &entry.original
+            } else if unchanged(&entry.keeper)? {
+                &entry.keeper
+            } else {
+                return conflict(format!(
+                    "its contents were replaced by a link to {:?}, which isn't an exact copy",
+                    entry.keeper
+                ));
+            };
+            let mut tmp_name = entry.original.as_os_str().to_os_string();
+            tmp_name.push(".image-dedup-restore");
+            let tmp = PathBuf::from(tmp_name);
+            fs::copy(source, &tmp)?;
+            fs::rename(&tmp, &entry.original).inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+            })
+        }
+        Action::Delete => conflict("it was deleted for good".to_string()),
+        Action::None => Ok(()),
+    }
+}
@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ImageProperties;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Decides which image of a duplicate group stays where it is.
+pub enum KeepPolicy {
+    #[default]
+    Largest,
+    Smallest,
+    Oldest,
+    Newest,
+    HighestResolution,
+    ShortestPath,
+}
+
+impl KeepPolicy {
+    /// Orders two images so the one that should be kept comes first.
+    fn compare(&self, a: &ImageProperties, b: &ImageProperties) -> Ordering {
+        // Files without a modification time always lose against ones that have one
+        let by_time = |a: &ImageProperties, b: &ImageProperties| match (a.modified, b.modified) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        };
+
+        match self {
+            KeepPolicy::Largest => b.file_size.cmp(&a.file_size),
+            KeepPolicy::Smallest => a.file_size.cmp(&b.file_size),
+            KeepPolicy::Oldest => by_time(a, b),
+            KeepPolicy::Newest => by_time(b, a),
+            KeepPolicy::HighestResolution => {
+                let pixels = |img: &ImageProperties| img.width as u64 * img.height as u64;
+                pixels(b).cmp(&pixels(a))
+            }
+            KeepPolicy::ShortestPath => {
+                let len = |img: &ImageProperties| img.path.as_os_str().len();
+                len(a).cmp(&len(b))
+            }
+        }
+    }
+
+    /// Picks the index of the image to keep out of `entries`, preferring images with a lower
+    /// `priority` before applying the policy. Ties go to whichever image comes first.
+    pub fn choose<P>(&self, entries: &[ImageProperties], priority: P) -> usize
+    where
+        P: Fn(&ImageProperties) -> usize,
+    {
+        let mut keeper = 0;
+        for (idx, img) in entries.iter().enumerate().skip(1) {
+            let best = &entries[keeper];
+            let ordering = priority(img)
+                .cmp(&priority(best))
+                .then_with(|| self.compare(img, best));
+            if ordering == Ordering::Less {
+                keeper = idx;
+            }
+        }
+        keeper
+    }
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "largest" => Ok(KeepPolicy::Largest),
+            "smallest" => Ok(KeepPolicy::Smallest),
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "newest" => Ok(KeepPolicy::Newest),
+            "highest-resolution" => Ok(KeepPolicy::HighestResolution),
+            "shortest-path" => Ok(KeepPolicy::ShortestPath),
+            _ => Err(format!(
+                "unknown keep policy {:?}, expected one of: largest, smallest, oldest, newest, highest-resolution, shortest-path",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeepPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeepPolicy::Largest => "largest",
+            KeepPolicy::Smallest => "smallest",
+            KeepPolicy::Oldest => "oldest",
+            KeepPolicy::Newest => "newest",
+            KeepPolicy::HighestResolution => "highest-resolution",
+            KeepPolicy::ShortestPath => "shortest-path",
+        };
+        write!(f, "{}", name)
+    }
+}
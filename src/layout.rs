@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::action::Action;
+use crate::scan::{Scanner, DUPLICATES_DIR};
+use crate::{DuplicateGroup, ImageProperties};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How duplicates are arranged inside the `duplicates` directory (or `--output-dir`).
+pub enum Layout {
+    /// The same relative path the duplicate had in the scanned directory.
+    Mirror,
+    /// A directory per group, named after the group's number in reports.
+    Group,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mirror" => Ok(Layout::Mirror),
+            "group" => Ok(Layout::Group),
+            _ => Err(format!(
+                "unknown layout {:?}, expected one of: mirror, group",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Mirror => write!(f, "mirror"),
+            Layout::Group => write!(f, "group"),
+        }
+    }
+}
+
+impl Layout {
+    /// Where `img`, part of group number `group`, would go if nothing was in the way. Duplicates
+    /// go into `output_dir` if there is one, otherwise into the `duplicates` directory of the
+    /// directory they were found in.
+    fn destination(
+        &self,
+        img: &ImageProperties,
+        group: usize,
+        output_dir: Option<&Path>,
+    ) -> Option<PathBuf> {
+        let duplicates = match output_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => img.root.join(DUPLICATES_DIR),
+        };
+        match self {
+            Layout::Mirror => match img.path.strip_prefix(&img.root) {
+                Ok(relative) if relative.file_name().is_some() => Some(duplicates.join(relative)),
+                _ => img.path.file_name().map(|name| duplicates.join(name)),
+            },
+            Layout::Group => img
+                .path
+                .file_name()
+                .map(|name| duplicates.join(group.to_string()).join(name)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Where every duplicate of every group goes, worked out before anything is touched so dry runs,
+/// reports and the real thing all agree.
+pub struct Plan {
+    destinations: HashMap<PathBuf, PathBuf>,
+}
+
+impl Plan {
+    /// Plans a destination for every duplicate in `groups`, if `action` puts duplicates anywhere at
+    /// all. Destinations that already exist, or that an earlier duplicate got, are numbered instead
+    /// (`photo.2.jpg`, `photo.3.jpg`, ...), so nothing is ever overwritten and the same files
+    /// always end up with the same names.
+    pub fn new(
+        groups: &[DuplicateGroup],
+        action: Action,
+        layout: Layout,
+        output_dir: Option<&Path>,
+    ) -> Plan {
+        if !action.relocates() {
+            return Plan::default();
+        }
+
+        let mut destinations = HashMap::new();
+        let mut claimed = HashSet::new();
+        for (idx, group) in groups.iter().enumerate() {
+            for img in group.duplicates() {
+                if destinations.contains_key(&img.path) {
+                    continue;
+                }
+                let base = match layout.destination(img, idx, output_dir) {
+                    Some(base) => base,
+                    None => continue,
+                };
+                let taken = |path: &Path| claimed.contains(path) || path.symlink_metadata().is_ok();
+
+                let mut destination = base.clone();
+                let mut number = 2;
+                while taken(&destination) {
+                    destination = numbered_path(&base, number);
+                    number += 1;
+                }
+                claimed.insert(destination.clone());
+                destinations.insert(img.path.clone(), destination);
+            }
+        }
+        Plan { destinations }
+    }
+
+    /// The destination planned for `img`, if it's a duplicate that has one.
+    pub fn destination(&self, img: &ImageProperties) -> Option<&Path> {
+        self.destinations.get(&img.path).map(PathBuf::as_path)
+    }
+}
+
+/// Makes `path` absolute and resolves any symlinks in it, even if the end of it doesn't exist yet.
+pub fn resolve_path(path: &Path) -> Result<PathBuf, io::Error> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(canonical) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(canonical, |path, name| path.join(name)));
+            }
+            Err(e) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = if parent.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        parent
+                    };
+                }
+                _ => return Err(e),
+            },
+        }
+    }
+}
+
+/// Refuses an `output_dir` that the scan of any of `roots` would look in, duplicates put there
+/// would be found again by the next run.
+pub fn check_output_dir(
+    output_dir: &Path,
+    roots: &[&Path],
+    scanner: &Scanner,
+) -> Result<(), String> {
+    let output = resolve_path(output_dir)
+        .map_err(|e| format!("Can't use {:?} as output directory: {}", output_dir, e))?;
+    for root in roots {
+        // Directories that can't be resolved can't be scanned either, that's reported later
+        let relative = match fs::canonicalize(root) {
+            Ok(canonical) => match output.strip_prefix(&canonical) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => continue,
+            },
+            Err(_) => continue,
+        };
+        if scanner.scans_dir(&relative) {
+            return Err(format!(
+                "Refusing to put duplicates in {:?}, {:?} would scan them again. Pick another \
+                 directory or exclude it from the scan",
+                output_dir, root
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `path` with `number` worked into its file name, see `numbered_name`.
+fn numbered_path(path: &Path, number: u32) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(numbered_name(&name, number))
+}
+
+/// `photo.jpg` becomes `photo.2.jpg` for `number` 2, names without an extension just get `.2`.
+pub fn numbered_name(name: &str, number: u32) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}.{}{}", &name[..dot], number, &name[dot..]),
+        _ => format!("{}.{}", name, number),
+    }
+}
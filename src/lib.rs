@@ -0,0 +1,711 @@
+//! Finding duplicate images, and doing something about them.
+//!
+//! [`find_duplicates`] does the finding: a [`Scanner`] decides which files under each directory
+//! are looked at, [`HashOptions`] decide how images are hashed and compared, and the results come
+//! back as [`DuplicateGroup`]s. An [`Executor`] then applies an [`Action`] to every duplicate that
+//! isn't kept, optionally recording it in a [`Journal`] so it can be undone.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use image_dedup_cli::{find_duplicates, Action, DedupOptions, Executor, Layout};
+//!
+//! let options = DedupOptions::default();
+//! let groups = find_duplicates(&[Path::new("photos")], &options, None, &()).groups;
+//! let mut executor = Executor::new(&groups, Action::Move, Layout::Mirror, None);
+//! executor.execute(&groups, |_, _, _, result| {
+//!     if let Err(err) = result {
+//!         eprintln!("{}", err);
+//!     }
+//! });
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use image::{DynamicImage, GenericImageView, ImageResult};
+use rayon::prelude::*;
+
+use crate::bktree::BkTree;
+use crate::cache::{CachedImage, FileStamp};
+
+pub use crate::action::{Action, Executor};
+pub use crate::cache::HashCache;
+pub use crate::error::Error;
+pub use crate::hash::{HashAlgorithm, HashOptions, ImageHasher, PerceptualHash, ResizeFilter};
+pub use crate::index::{Index, IndexUpdate};
+pub use crate::journal::Journal;
+pub use crate::keep::KeepPolicy;
+pub use crate::layout::{Layout, Plan};
+pub use crate::progress::Progress;
+pub use crate::scan::{Formats, Scanner};
+pub use crate::transform::Transform;
+pub use crate::watch::Watch;
+
+pub mod action;
+mod bktree;
+pub mod cache;
+pub mod crop;
+mod error;
+mod exact;
+pub mod hash;
+pub mod index;
+pub mod journal;
+pub mod keep;
+pub mod layout;
+pub mod progress;
+pub mod report;
+pub mod scan;
+pub mod transform;
+pub mod trash;
+pub mod watch;
+
+#[derive(Clone, Debug)]
+/// Various properties to describe an image.
+pub struct ImageProperties {
+    pub path: PathBuf,
+    /// The scanned directory this image was found under.
+    pub root: PathBuf,
+    pub file_size: u64,
+    /// Last modification time, if the platform and filesystem provide one.
+    pub modified: Option<SystemTime>,
+    pub width: u32,
+    pub height: u32,
+    pub hash: PerceptualHash,
+    /// Hashes of the image's other orientations, in `Transform::ALL` order after `Identity`. Only
+    /// made with rotation invariant hash options, empty otherwise.
+    pub transformed: Vec<PerceptualHash>,
+    /// Hashes of every `crop::windows()` of the image, in that order. Only made with hash options
+    /// that detect crops, empty otherwise.
+    pub crops: Vec<PerceptualHash>,
+}
+
+impl ImageProperties {
+    /// The window of this image that looks most like `other`, and how many hash bits apart they
+    /// are, if crops of this image were hashed and `other` is big enough to be a crop.
+    pub fn closest_crop(&self, other: &ImageProperties) -> Option<(crop::Window, u32)> {
+        if !crop::is_big_enough(other.width, other.height) {
+            return None;
+        }
+        crop::windows()
+            .into_iter()
+            .zip(&self.crops)
+            .map(|(window, hash)| (window, hash.distance(&other.hash)))
+            .min_by_key(|(_, distance)| *distance)
+    }
+
+    /// Every hash of the image, each with the orientation it's the hash of.
+    pub fn hashes(&self) -> impl Iterator<Item = (Transform, &PerceptualHash)> {
+        Transform::ALL
+            .iter()
+            .copied()
+            .zip(std::iter::once(&self.hash).chain(&self.transformed))
+    }
+
+    /// The orientation of this image that looks most like `other`, and how many hash bits apart
+    /// they are. Always `Identity` unless the images were hashed rotation invariant.
+    pub fn closest_transform(&self, other: &ImageProperties) -> (Transform, u32) {
+        self.hashes()
+            .map(|(transform, hash)| (transform, hash.distance(&other.hash)))
+            .min_by_key(|(_, distance)| *distance)
+            .unwrap_or((Transform::Identity, u32::MAX))
+    }
+
+    /// Properties of a byte-identical copy of this image, found at `path` under `root`.
+    fn identical_copy(&self, path: PathBuf, root: &Path) -> ImageProperties {
+        ImageProperties {
+            modified: fs::metadata(&path).and_then(|md| md.modified()).ok(),
+            path,
+            root: root.to_path_buf(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Clusters images whose hashes are at most `threshold` bits apart, only returning clusters with
+/// more than one image in them. Images hashed rotation invariant also cluster with the images
+/// that are close to any of their orientations.
+///
+/// Clustering is transitive: if A is close to B and B is close to C, all three end up in the same
+/// group even if A and C are further than `threshold` apart.
+fn group_duplicates(images: Vec<ImageProperties>, threshold: u32) -> Vec<Vec<ImageProperties>> {
+    let rotations = images.iter().any(|img| !img.transformed.is_empty());
+    let mut hashes: HashMap<PerceptualHash, Vec<ImageProperties>> = HashMap::new();
+    for img_prop in images {
+        // Gets an Entry from the hash map, which is an enum that can be: OccupiedEntry, VacantEntry.
+        // Adds a new vector if it's a VacantEntry.
+        // https://doc.rust-lang.org/std/collections/hash_map/enum.Entry.html#method.or_default
+        hashes
+            .entry(img_prop.hash.clone())
+            .or_default()
+            .push(img_prop);
+    }
+
+    let mut groups: Vec<Vec<ImageProperties>> = if threshold == 0 && !rotations {
+        // Exact matching doesn't need the tree, every bucket is already a group
+        hashes.into_values().collect()
+    } else {
+        merge_near_buckets(hashes.into_values().collect(), threshold)
+    };
+
+    groups.retain(|entries| entries.len() > 1);
+    for entries in &mut groups {
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    groups
+}
+
+/// Merges buckets of identical hashes whose hashes are at most `threshold` bits apart.
+fn merge_near_buckets(
+    buckets: Vec<Vec<ImageProperties>>,
+    threshold: u32,
+) -> Vec<Vec<ImageProperties>> {
+    let mut tree = BkTree::new();
+    for (idx, bucket) in buckets.iter().enumerate() {
+        tree.insert(bucket[0].hash.clone(), idx);
+    }
+
+    // Union-find over the buckets, every near neighbour gets merged into the same set
+    let mut parents: Vec<usize> = (0..buckets.len()).collect();
+    fn root(parents: &mut [usize], mut idx: usize) -> usize {
+        while parents[idx] != idx {
+            parents[idx] = parents[parents[idx]];
+            idx = parents[idx];
+        }
+        idx
+    }
+    for (idx, bucket) in buckets.iter().enumerate() {
+        for (_, hash) in bucket[0].hashes() {
+            for &neighbour in tree.find(hash, threshold) {
+                let (a, b) = (root(&mut parents, idx), root(&mut parents, neighbour));
+                if a != b {
+                    parents[a] = b;
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<ImageProperties>> = HashMap::new();
+    for (idx, bucket) in buckets.into_iter().enumerate() {
+        groups
+            .entry(root(&mut parents, idx))
+            .or_default()
+            .extend(bucket);
+    }
+    groups.into_values().collect()
+}
+
+/// Hashes every image in `files`, each paired with the directory it was found under. Images that
+/// can't be opened end up as errors, everything stays in the same order.
+///
+/// Images are decoded and hashed in parallel on the rayon thread pool, one image per thread at a
+/// time, so the number of threads also caps how many decoded images are in memory at once.
+fn hash_images(
+    files: Vec<(PathBuf, &Path)>,
+    hash_options: &HashOptions,
+    cache: Option<&Mutex<HashCache>>,
+    progress: &dyn Progress,
+) -> Vec<Result<ImageProperties, Error>> {
+    files
+        .into_par_iter()
+        .map_init(
+            || hash_options.hasher(),
+            |hasher, (image, root)| hash_image(image, root, hasher, cache, progress),
+        )
+        .collect()
+}
+
+/// Opens the image at `path`, working out its format from its contents.
+pub fn open_image(path: &Path) -> ImageResult<DynamicImage> {
+    image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()
+}
+
+/// Opens the image at `path` the way it's hashed with `options`: upright according to its EXIF
+/// orientation, unless that's turned off. Its width and height are reported that way up too.
+pub fn open_upright(path: &Path, options: &HashOptions) -> ImageResult<DynamicImage> {
+    let image = open_image(path)?;
+    if !options.exif_orientation {
+        return Ok(image);
+    }
+    Ok(match transform::exif_orientation(path) {
+        Transform::Identity => image,
+        transform => transform.apply(&image),
+    })
+}
+
+/// Opens the image at `path` the way `hasher` wants it hashed, which is upright according to its
+/// EXIF orientation unless that's turned off, and without its borders if they're trimmed. The
+/// upright width and height come along, since those are the image's size whatever its borders.
+fn open_for_hashing(path: &Path, hasher: &ImageHasher) -> ImageResult<(DynamicImage, (u32, u32))> {
+    let mut image = open_upright(path, hasher.options())?;
+    let dimensions = image.dimensions();
+    if hasher.options().trim_borders {
+        image = crop::trim_borders(&image);
+    }
+    Ok((image, dimensions))
+}
+
+/// Hashes a single image found under `root`, telling `progress` how that went.
+///
+/// If the image is in `cache` and hasn't changed since, it's never opened, otherwise it's added to it.
+fn hash_image(
+    image: PathBuf,
+    root: &Path,
+    hasher: &ImageHasher,
+    cache: Option<&Mutex<HashCache>>,
+    progress: &dyn Progress,
+) -> Result<ImageProperties, Error> {
+    let metadata = fs::metadata(&image).ok();
+
+    // Only bother canonicalizing if there's a cache to look the image up in
+    let cache_key = match (cache, metadata.as_ref().and_then(FileStamp::new)) {
+        (Some(cache), Some(stamp)) => fs::canonicalize(&image)
+            .ok()
+            .map(|canonical| (cache, canonical, stamp)),
+        _ => None,
+    };
+    let cached = cache_key
+        .as_ref()
+        .and_then(|(cache, canonical, stamp)| cache.lock().unwrap().get(canonical, *stamp));
+
+    let (width, height, hash, transformed, crops) = match cached {
+        Some(cached) => {
+            progress.hashed(&image, true);
+            (
+                cached.width,
+                cached.height,
+                cached.hash,
+                cached.transformed,
+                cached.crops,
+            )
+        }
+        None => {
+            let (image_obj, (width, height)) = match open_for_hashing(&image, hasher) {
+                Ok(img) => img,
+                Err(e) => {
+                    let err = Error::opening(image, e);
+                    progress.failed(&err);
+                    return Err(err);
+                }
+            };
+            let (hash, transformed) = hasher.hash_transforms(&image_obj);
+            let crops = hasher.hash_crops(&image_obj);
+            progress.hashed(&image, false);
+
+            if let Some((cache, canonical, stamp)) = cache_key {
+                cache.lock().unwrap().insert(
+                    canonical,
+                    CachedImage {
+                        stamp,
+                        width,
+                        height,
+                        hash: hash.clone(),
+                        transformed: transformed.clone(),
+                        crops: crops.clone(),
+                    },
+                );
+            }
+            (width, height, hash, transformed, crops)
+        }
+    };
+
+    Ok(ImageProperties {
+        file_size: metadata.as_ref().map(|md| md.len()).unwrap_or(0),
+        modified: metadata.and_then(|md| md.modified().ok()),
+        width,
+        height,
+        path: image,
+        root: root.to_path_buf(),
+        hash,
+        transformed,
+        crops,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How the images of a duplicate group were matched up.
+pub enum MatchKind {
+    /// Byte-for-byte identical files.
+    Exact,
+    /// Images with the same or similar perceptual hashes.
+    Perceptual,
+    /// One image is a crop of the other.
+    Crop,
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchKind::Exact => write!(f, "exact"),
+            MatchKind::Perceptual => write!(f, "perceptual"),
+            MatchKind::Crop => write!(f, "crop"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A set of images that are duplicates of each other, along with the one that stays in place.
+pub struct DuplicateGroup {
+    /// Every image of the group, sorted by path.
+    pub images: Vec<ImageProperties>,
+    /// Index into `images` of the image that is kept.
+    pub keeper: usize,
+    pub kind: MatchKind,
+}
+
+impl DuplicateGroup {
+    pub fn keeper(&self) -> &ImageProperties {
+        &self.images[self.keeper]
+    }
+
+    /// How `img` relates to the keeper: the orientation of the keeper that looks most like it, and
+    /// how many hash bits apart they are. Crops are only looked for the right way up, so crop
+    /// groups are always `Identity` and give the distance of the best matching window.
+    pub fn relation(&self, img: &ImageProperties) -> (Transform, u32) {
+        match self.crop_window() {
+            Some((_, distance)) => (Transform::Identity, distance),
+            None => self.keeper().closest_transform(img),
+        }
+    }
+
+    /// For crop groups, how much of the larger image the crop covers, from 0 to 1.
+    pub fn overlap(&self) -> Option<f64> {
+        self.crop_window().map(|(window, _)| window.area())
+    }
+
+    /// The window of one image that the other is a crop of, if this is a crop group.
+    fn crop_window(&self) -> Option<(crop::Window, u32)> {
+        match (self.kind, &self.images[..]) {
+            (MatchKind::Crop, [a, b]) => {
+                let windows = a.closest_crop(b).into_iter().chain(b.closest_crop(a));
+                windows.min_by_key(|(_, distance)| *distance)
+            }
+            _ => None,
+        }
+    }
+
+    /// Every image of the group except the keeper.
+    pub fn duplicates(&self) -> impl Iterator<Item = &ImageProperties> {
+        self.images
+            .iter()
+            .enumerate()
+            .filter(move |(idx, _)| *idx != self.keeper)
+            .map(|(_, img)| img)
+    }
+}
+
+#[derive(Debug, Default)]
+/// What `find_duplicates` came up with.
+pub struct Duplicates {
+    pub groups: Vec<DuplicateGroup>,
+    /// Every file or directory that had to be skipped, and why.
+    pub skipped: Vec<Error>,
+}
+
+#[derive(Debug, Default)]
+/// Everything that decides which images count as duplicates, and which of them is kept.
+pub struct DedupOptions {
+    pub scanner: Scanner,
+    pub hash: HashOptions,
+    /// Maximum number of differing hash bits for two images to count as duplicates.
+    pub threshold: u32,
+    pub keep: KeepPolicy,
+}
+
+/// Finds the duplicates across all of `roots`.
+///
+/// Byte-identical files are found first, and only one file out of each identical set is decoded
+/// and hashed, the others are given its hash. The keeper of each identical set then goes on to be
+/// compared perceptually with every other image. The image kept from each group always comes from
+/// the earliest root that has a copy. With `detect_crops`, images that are a crop of another one
+/// are then paired up in groups of their own, unless they're already grouped together.
+///
+/// Hashes are looked up in and added to `cache` if there is one. Groups are sorted by the path of
+/// their first image, so the same files always give the same groups in the same order.
+///
+/// Files that can't be read or decoded are left out of the groups, and returned with them instead.
+/// `progress` is told about every image along the way, pass `&()` if nobody's watching.
+pub fn find_duplicates(
+    roots: &[&Path],
+    options: &DedupOptions,
+    cache: Option<&Mutex<HashCache>>,
+    progress: &dyn Progress,
+) -> Duplicates {
+    let mut skipped = Vec::new();
+    let files = scan_roots(roots, &options.scanner, progress, &mut skipped);
+    let (identical, unique) = exact::split_identical(files, |(path, _)| path.as_path());
+
+    let priority = |img: &ImageProperties| {
+        roots
+            .iter()
+            .position(|root| *root == img.root)
+            .unwrap_or(usize::MAX)
+    };
+    let choose_keepers = |groups: Vec<Vec<ImageProperties>>, kind| {
+        groups
+            .into_iter()
+            .map(|images| DuplicateGroup {
+                keeper: options.keep.choose(&images, priority),
+                images,
+                kind,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The first file of each identical set stands in for the rest of it
+    let representatives = identical.iter().map(|set| set[0].clone()).collect();
+    let identical: Vec<Vec<ImageProperties>> = identical
+        .into_iter()
+        .zip(hash_images(representatives, &options.hash, cache, progress))
+        .filter_map(|(set, representative)| {
+            progress.identical(set.len() - 1);
+            match representative {
+                Ok(representative) => Some(
+                    set.into_iter()
+                        .map(|(path, root)| representative.identical_copy(path, root))
+                        .collect(),
+                ),
+                // The copies can't be any better, and the error already names one of them
+                Err(e) => {
+                    skipped.push(e);
+                    None
+                }
+            }
+        })
+        .collect();
+    let mut groups = choose_keepers(identical, MatchKind::Exact);
+
+    let mut images = Vec::new();
+    for hashed in hash_images(unique, &options.hash, cache, progress) {
+        match hashed {
+            Ok(img) => images.push(img),
+            Err(e) => skipped.push(e),
+        }
+    }
+    images.extend(groups.iter().map(|group| group.keeper().clone()));
+    let crop_candidates = if options.hash.detect_crops {
+        images.clone()
+    } else {
+        Vec::new()
+    };
+    groups.extend(choose_keepers(
+        group_duplicates(images, options.threshold),
+        MatchKind::Perceptual,
+    ));
+    let crops = find_crops(crop_candidates, &groups, options.threshold);
+    groups.extend(choose_keepers(crops, MatchKind::Crop));
+
+    // Groups come out of hash maps, sorting them keeps group numbers and destinations the same
+    // from one run to the next
+    groups.sort_by(|a, b| a.images[0].path.cmp(&b.images[0].path));
+    Duplicates { groups, skipped }
+}
+
+/// Pairs up every image in `images` that's a crop of another one, with the image whose window it
+/// matches best. Images that are already in one of `groups` together aren't paired again.
+fn find_crops(
+    images: Vec<ImageProperties>,
+    groups: &[DuplicateGroup],
+    threshold: u32,
+) -> Vec<Vec<ImageProperties>> {
+    let mut tree = BkTree::new();
+    for (idx, img) in images.iter().enumerate() {
+        tree.insert(img.hash.clone(), idx);
+    }
+
+    // The closest original of every crop, as (distance, original)
+    let mut originals: HashMap<usize, (u32, usize)> = HashMap::new();
+    for (original, img) in images.iter().enumerate() {
+        for window in &img.crops {
+            for &crop in tree.find(window, threshold) {
+                let img = &images[crop];
+                if crop == original || !crop::is_big_enough(img.width, img.height) {
+                    continue;
+                }
+                let distance = window.distance(&images[crop].hash);
+                let best = originals.entry(crop).or_insert((distance, original));
+                if (distance, original) < *best {
+                    *best = (distance, original);
+                }
+            }
+        }
+    }
+
+    let mut grouped: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (idx, group) in groups.iter().enumerate() {
+        for img in &group.images {
+            grouped.entry(&img.path).or_default().push(idx);
+        }
+    }
+    let together = |a: &Path, b: &Path| match (grouped.get(a), grouped.get(b)) {
+        (Some(a), Some(b)) => a.iter().any(|group| b.contains(group)),
+        _ => false,
+    };
+
+    let mut pairs = HashSet::new();
+    originals
+        .into_iter()
+        .map(|(crop, (_, original))| (crop.min(original), crop.max(original)))
+        // Two images can both look like a crop of the other, they're still only one pair
+        .filter(|pair| pairs.insert(*pair))
+        .filter(|(a, b)| !together(&images[*a].path, &images[*b].path))
+        .map(|(a, b)| {
+            let mut pair = vec![images[a].clone(), images[b].clone()];
+            pair.sort_by(|a, b| a.path.cmp(&b.path));
+            pair
+        })
+        .collect()
+}
+
+/// Finds the images under `queries` that already exist in `reference`, which is only ever read.
+///
+/// Every query image is matched with the reference image whose hash is closest, if it's within
+/// `options.threshold` bits. Each match comes back as a group of two with the reference image
+/// first and kept, so acting on the groups only ever touches the query side. Query images that
+/// only match each other aren't reported, and `options.keep` doesn't apply.
+pub fn find_matches(
+    queries: &[&Path],
+    reference: &Path,
+    options: &DedupOptions,
+    cache: Option<&Mutex<HashCache>>,
+    progress: &dyn Progress,
+) -> Duplicates {
+    let mut skipped = Vec::new();
+    let references = scan_roots(&[reference], &options.scanner, progress, &mut skipped);
+    let queries = scan_roots(queries, &options.scanner, progress, &mut skipped);
+
+    let mut hashed = |files| {
+        let mut images = Vec::new();
+        for hashed in hash_images(files, &options.hash, cache, progress) {
+            match hashed {
+                Ok(img) => images.push(img),
+                Err(e) => skipped.push(e),
+            }
+        }
+        images
+    };
+    let references = hashed(references);
+    let queries = hashed(queries);
+    let groups = match_queries(&references, queries, options.threshold);
+    Duplicates { groups, skipped }
+}
+
+/// Like `find_matches`, but with the images of `index` as the reference, so only the queries are
+/// scanned. Queries are hashed with the index's hash options rather than `options.hash`, `cache`
+/// has to be the cache for those.
+///
+/// Images removed from the collection since the index was last updated are left out, but the
+/// index isn't otherwise checked against it.
+pub fn find_matches_in_index(
+    queries: &[&Path],
+    index: &Index,
+    options: &DedupOptions,
+    cache: Option<&Mutex<HashCache>>,
+    progress: &dyn Progress,
+) -> Duplicates {
+    let mut skipped = Vec::new();
+    let queries = scan_roots(queries, &options.scanner, progress, &mut skipped);
+    let mut images = Vec::new();
+    for hashed in hash_images(queries, index.options(), cache, progress) {
+        match hashed {
+            Ok(img) => images.push(img),
+            Err(e) => skipped.push(e),
+        }
+    }
+
+    // Only images that are still there can be kept
+    let mut references = index.images();
+    references.retain(|img| img.path.is_file());
+    let groups = match_queries(&references, images, options.threshold);
+    Duplicates { groups, skipped }
+}
+
+/// Pairs every image in `queries` with the closest image in `references` within `threshold`, see
+/// `find_matches`.
+fn match_queries(
+    references: &[ImageProperties],
+    queries: Vec<ImageProperties>,
+    threshold: u32,
+) -> Vec<DuplicateGroup> {
+    let mut tree = BkTree::new();
+    for (idx, img) in references.iter().enumerate() {
+        tree.insert(img.hash.clone(), idx);
+    }
+    let mut groups: Vec<DuplicateGroup> = queries
+        .into_par_iter()
+        .filter_map(|query| {
+            let mut found: Vec<usize> = query
+                .hashes()
+                .flat_map(|(_, hash)| tree.find(hash, threshold))
+                .copied()
+                .collect();
+            found.sort_unstable();
+            found.dedup();
+            // The query directory can be inside the reference, but nothing is a copy of itself
+            let candidates = found
+                .into_iter()
+                .map(|idx| &references[idx])
+                .filter(|img| !action::is_same_file(&img.path, &query.path).unwrap_or(false))
+                .collect();
+            let (closest, kind) = closest_match(&query, candidates)?;
+            Some(DuplicateGroup {
+                images: vec![closest.clone(), query],
+                keeper: 0,
+                kind,
+            })
+        })
+        .collect();
+    groups.sort_by(|a, b| a.images[1].path.cmp(&b.images[1].path));
+    groups
+}
+
+/// Picks the image out of `candidates` that `image` is a duplicate of, along with how they match.
+/// Candidates all have to be within the threshold already, the closest one is picked with ties
+/// going to the first path, but an identical copy beats anything that merely looks the same.
+pub(crate) fn closest_match<'a>(
+    image: &ImageProperties,
+    mut candidates: Vec<&'a ImageProperties>,
+) -> Option<(&'a ImageProperties, MatchKind)> {
+    candidates.sort_by_key(|img| (img.closest_transform(image).1, &img.path));
+    match candidates
+        .iter()
+        .find(|img| exact::is_identical(&img.path, &image.path))
+    {
+        Some(identical) => Some((*identical, MatchKind::Exact)),
+        None => Some((*candidates.first()?, MatchKind::Perceptual)),
+    }
+}
+
+/// Finds every image under `roots`, each paired with the root it was found under, adding anything
+/// that had to be skipped to `skipped`.
+fn scan_roots<'a>(
+    roots: &[&'a Path],
+    scanner: &Scanner,
+    progress: &dyn Progress,
+    skipped: &mut Vec<Error>,
+) -> Vec<(PathBuf, &'a Path)> {
+    let found: Vec<_> = roots
+        .par_iter()
+        .map(|root| (*root, scanner.find_images(root)))
+        .collect();
+    // Roots can overlap or repeat, and a file found under two of them (or linked into both) is
+    // still only one file. It stays with the earliest root, like it would if it were a copy
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for (root, (images, errors)) in found {
+        let images: Vec<PathBuf> = images
+            .into_iter()
+            .filter(|path| scan::file_id(path).map_or(true, |id| seen.insert(id)))
+            .collect();
+        progress.found(images.len());
+        files.extend(images.into_iter().map(|path| (path, root)));
+        skipped.extend(errors);
+    }
+    files
+}
@@ -1,137 +1,954 @@
-use std::collections::HashMap;
-use std::ffi::OsString;
-use std::io::ErrorKind;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use argh::FromArgs;
-use glob::glob;
-use image::imageops::FilterType;
-use img_hash::{HashAlg, HasherConfig};
+use argh::{EarlyExit, FromArgs};
+use crossterm::tty::IsTty;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
 use rayon::prelude::*;
 
-const VALID_EXTENSIONS: [&str; 4] = ["png", "jpeg", "jpg", "gif"];
+use image_dedup_cli::report::{self, ReportFormat};
+use image_dedup_cli::scan::parse_patterns;
+use image_dedup_cli::{
+    find_duplicates, find_matches, find_matches_in_index, journal, layout, Action, DedupOptions,
+    DuplicateGroup, Duplicates, Error, Executor, Formats, HashAlgorithm, HashCache, HashOptions,
+    Index, Journal, KeepPolicy, Layout, ResizeFilter, Scanner, Transform, Watch,
+};
+
+use crate::output::{Output, Verbosity};
+
+mod html;
+mod output;
+mod review;
 
 #[derive(FromArgs, Debug)]
 /// Command-line arguments for image deduplication.
 struct Args {
-    /// one or more directories to check for duplicates in, each directory is checked independently
+    /// one or more directories to check for duplicates in, each directory is checked independently unless `--cross` is given
+    #[argh(positional)]
+    directories: Vec<String>,
+
+    /// check all directories against each other, keeping the copy from the directory listed first
+    #[argh(switch)]
+    cross: bool,
+
+    /// which image of each group to leave in place: largest, smallest, oldest, newest,
+    /// highest-resolution or shortest-path (default: largest)
+    #[argh(option, default = "KeepPolicy::Largest")]
+    keep: KeepPolicy,
+
+    /// what to do with every duplicate that isn't kept: move (into `duplicates`), copy, hardlink or
+    /// symlink (replacing it with a link to the kept image), delete, trash or none (default: move)
+    #[argh(option, default = "Action::Move")]
+    action: Action,
+
+    /// how moved or copied duplicates are arranged in `duplicates`: mirror (their path relative to
+    /// the scanned directory) or group (a directory per group) (default: mirror)
+    #[argh(option, default = "Layout::Mirror")]
+    layout: Layout,
+
+    /// put moved or copied duplicates here instead of a `duplicates` directory in each scanned
+    /// directory, can be on another filesystem but can't be scanned itself
+    #[argh(option)]
+    output_dir: Option<String>,
+
+    /// where to record every change made to files, so they can be undone with `undo` (default: a new
+    /// file in $XDG_DATA_HOME/image-dedup-cli/journals)
+    #[argh(option)]
+    journal: Option<String>,
+
+    /// review every group in the terminal first, picking which image to keep or skipping the group,
+    /// nothing happens to any file until every group is reviewed
+    #[argh(switch)]
+    interactive: bool,
+
+    /// scan and hash as usual, but only print what would be done instead of touching any files
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// write every duplicate group to stdout as json or csv
+    #[argh(option)]
+    report: Option<ReportFormat>,
+
+    /// write a self-contained html page with thumbnails of every duplicate group to this file
+    #[argh(option)]
+    html_report: Option<String>,
+
+    /// maximum number of differing hash bits for two images to count as duplicates (default: 0)
+    #[argh(option, default = "0")]
+    threshold: u32,
+
+    /// perceptual hash to compare images with: mean, gradient, vert-gradient, double-gradient,
+    /// blockhash or dct (default: gradient)
+    #[argh(option, default = "HashAlgorithm::Gradient")]
+    hash_alg: HashAlgorithm,
+
+    /// width and height of the hash in bits, larger hashes tell similar images apart better (default: 8)
+    #[argh(option, default = "8")]
+    hash_size: u32,
+
+    /// filter used to shrink images before hashing: nearest, triangle, catmull-rom, gaussian or
+    /// lanczos3 (default: triangle)
+    #[argh(option, default = "ResizeFilter::Triangle")]
+    resize_filter: ResizeFilter,
+
+    /// also match images that were rotated or mirrored, by hashing every orientation of each image
+    #[argh(switch)]
+    rotation_invariant: bool,
+
+    /// hash images the way their pixels are stored, rather than turning them upright according to
+    /// their EXIF orientation first
+    #[argh(switch)]
+    ignore_exif_orientation: bool,
+
+    /// cut off uniform borders, like letterboxing or a frame, before hashing images
+    #[argh(switch)]
+    trim_borders: bool,
+
+    /// also match images that are a crop of another one, by hashing regions of each image; slower
+    #[argh(switch)]
+    detect_crops: bool,
+
+    /// don't read or update the hash cache in $XDG_CACHE_HOME/image-dedup-cli
+    #[argh(switch)]
+    no_cache: bool,
+
+    /// maximum number of images to decode and hash at once, 0 uses one per CPU (default: 0)
+    #[argh(option, short = 'j', default = "0")]
+    jobs: usize,
+
+    /// also check images in subdirectories, skipping the `duplicates` directory
+    #[argh(switch, short = 'r')]
+    recursive: bool,
+
+    /// only check files matching this glob, patterns without a `/` match the file name (repeatable)
+    #[argh(option)]
+    include: Vec<String>,
+
+    /// skip files and directories matching this glob, patterns without a `/` match the file name (repeatable)
+    #[argh(option)]
+    exclude: Vec<String>,
+
+    /// comma separated image formats to check, detected from file contents rather than extensions:
+    /// png, jpeg, gif, webp, bmp, tiff, ico, pnm, hdr, farbfeld (default: all of them)
+    #[argh(option, default = "Formats::default()")]
+    formats: Formats,
+
+    /// only print errors, no progress or what happens to each duplicate
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+
+    /// also print every image as it's hashed or skipped, and a summary of the scan
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Undo(UndoArgs),
+    Check(CheckArgs),
+    Index(IndexArgs),
+    Watch(WatchArgs),
+}
+
+#[derive(FromArgs, Debug)]
+/// Put every file changed by an earlier run back where it was.
+#[argh(subcommand, name = "undo")]
+struct UndoArgs {
+    /// the journal written by the run to undo
+    #[argh(positional)]
+    journal: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// Look for images that already exist in a reference library, only ever acting on the images
+/// looked up. Every other option goes before `check`.
+#[argh(subcommand, name = "check")]
+struct CheckArgs {
+    /// directories with the images to look up
     #[argh(positional)]
     directories: Vec<String>,
+
+    /// the reference library to look the images up in, which is never changed: a directory, or an
+    /// index made with `index build`
+    #[argh(option)]
+    against: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// Keep the hashes of a collection in an index file, to look up images similar to another one
+/// without scanning the whole collection again.
+#[argh(subcommand, name = "index")]
+struct IndexArgs {
+    #[argh(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum IndexCommand {
+    Build(BuildArgs),
+    Update(UpdateArgs),
+    Query(QueryArgs),
+}
+
+#[derive(FromArgs, Debug)]
+/// Hash every image in a collection into a new index. The scanning and hashing options go before
+/// `index`, and are kept in the index for every update.
+#[argh(subcommand, name = "build")]
+struct BuildArgs {
+    /// the directory with the collection
+    #[argh(positional)]
+    collection: String,
+
+    /// where to write the index, replacing any file already there
+    #[argh(positional)]
+    index: String,
 }
 
-#[derive(Debug)]
-/// Various properties to describe an image.
-struct ImageProperties {
-    path: PathBuf,
-    file_size: u64,
+#[derive(FromArgs, Debug)]
+/// Hash the images added to or modified in a collection since its index was last built or updated,
+/// and drop the ones that are gone.
+#[argh(subcommand, name = "update")]
+struct UpdateArgs {
+    /// the index to update
+    #[argh(positional)]
+    index: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// Look up the images in an index that are similar to each of the given images, as close as
+/// `--threshold` (given before `index`) allows.
+#[argh(subcommand, name = "query")]
+struct QueryArgs {
+    /// the index to look the images up in
+    #[argh(positional)]
+    index: String,
+
+    /// images to look up
+    #[argh(positional)]
+    images: Vec<String>,
+}
+
+#[derive(FromArgs, Debug)]
+/// Keep checking a directory for duplicates as images land in it, until interrupted. New images are
+/// only compared with the ones already there, and the action is applied to duplicates straight
+/// away. Every other option goes before `watch`.
+#[argh(subcommand, name = "watch")]
+struct WatchArgs {
+    /// the directory to watch
+    #[argh(positional)]
+    directory: String,
+
+    /// seconds a file has to go unchanged before it's checked, so images still being written
+    /// aren't (default: 2)
+    #[argh(option, default = "2")]
+    settle: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// How a run went, doubling as the exit code so scripts can branch on it.
+enum Status {
+    /// No duplicates were found, or an `undo` or index update went through completely.
+    NoDuplicates = 0,
+    /// Duplicates were found, and acted on unless it was a dry run. For a query, similar images
+    /// were found.
+    DuplicatesFound = 1,
+    /// The run went through, but some files were skipped or couldn't be acted on.
+    PartialFailure = 2,
+    /// Nothing was done at all, because of bad arguments or something else that stops the run.
+    Fatal = 3,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> ExitCode {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// Same as `argh::from_env`, except bad arguments exit with `Status::Fatal` rather than argh's 1,
+/// which would look like duplicates were found.
+fn args_from_env() -> Result<Args, ExitCode> {
+    let strings: Vec<String> = env::args().collect();
+    let command = strings
+        .first()
+        .and_then(|cmd| Path::new(cmd).file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or("image-dedup-cli");
+    let strs: Vec<&str> = strings.iter().skip(1).map(String::as_str).collect();
+    Args::from_args(&[command], &strs).map_err(|EarlyExit { output, status }| match status {
+        // Asked for --help
+        Ok(()) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(()) => {
+            eprintln!("{}\nRun {} --help for more information.", output, command);
+            Status::Fatal.into()
+        }
+    })
+}
+
+/// Applies the executor's action to every duplicate in `groups`, or only prints what would be
+/// done if it's a dry run.
+///
+/// Returns how many duplicates the action failed on.
+fn apply_actions(
+    executor: &mut Executor,
+    groups: &[DuplicateGroup],
+    dry_run: bool,
+    output: &Output,
+) -> usize {
+    let mut failed = 0;
+    executor.execute(groups, |group, e, description, result| match result {
+        Ok(_) if dry_run => output.info(format_args!("Would {}", description)),
+        Ok(_) => {
+            let mut details = vec![group.kind.to_string()];
+            match group.relation(e).0 {
+                Transform::Identity => {}
+                transform => details.push(transform.to_string()),
+            }
+            if let Some(overlap) = group.overlap() {
+                details.push(format!("{:.0}% overlap", overlap * 100.0));
+            }
+            output.info(format_args!(
+                "{:?} duplicates {:?} ({})",
+                e.path,
+                group.keeper().path,
+                details.join(", ")
+            ))
+        }
+        Err(err) => {
+            output.error(err);
+            failed += 1;
+        }
+    });
+    if let Some(journal) = executor.journal() {
+        for err in executor.journal_errors() {
+            output.error(format_args!(
+                "Failed writing to journal {:?}: {}",
+                journal.path(),
+                err
+            ));
+        }
+    }
+    failed
 }
 
-fn find_duplicates(directory: &str) -> Result<(), io::Error> {
-    let owned_dir = directory.to_owned();
+fn main() -> ExitCode {
+    match args_from_env() {
+        Ok(args) => run(args).into(),
+        Err(code) => code,
+    }
+}
 
-    let dup_glob = match glob(&(owned_dir.clone() + r"/*.*")) {
-        Ok(g) => g,
-        Err(e) => return Err(io::Error::new(ErrorKind::Other, e.msg)),
+fn run(args: Args) -> Status {
+    let verbosity = match (args.quiet, args.verbose) {
+        (false, false) => Verbosity::Normal,
+        (true, false) => Verbosity::Quiet,
+        (false, true) => Verbosity::Verbose,
+        (true, true) => {
+            eprintln!("--quiet and --verbose can't be used together");
+            return Status::Fatal;
+        }
     };
-    let images: Vec<PathBuf> = dup_glob
-        .filter_map(Result::ok)
-        .filter(|pb| {
-            VALID_EXTENSIONS.contains(
-                &pb.extension()
-                    .unwrap_or(&OsString::new())
-                    .to_str()
-                    .unwrap_or(""),
-            )
-        })
-        .collect();
-
-    let mut hashes: HashMap<u64, Vec<ImageProperties>> = HashMap::new();
-    let hasher = HasherConfig::with_bytes_type::<[u8; 8]>()
-        .hash_alg(HashAlg::Gradient)
-        .resize_filter(FilterType::Triangle)
-        .to_hasher();
-
-    for image in &images {
-        let image_obj = match image::open(image) {
-            Ok(img) => img,
-            Err(e) => {
-                eprintln!("Skipping opening {:?}, original error: {}", image, e);
-                continue;
+    // Subcommands take their own directories, these would just be ignored
+    if args.command.is_some() && !args.directories.is_empty() {
+        eprintln!(
+            "{:?} can't be checked along with a subcommand, give directories after the subcommand instead",
+            args.directories
+        );
+        return Status::Fatal;
+    }
+    // The report owns stdout if there is one, so the usual messages are left out
+    let output = Output::new(verbosity, args.report.is_some());
+
+    if let Some(Command::Undo(undo)) = &args.command {
+        return match journal::undo(Path::new(&undo.journal)) {
+            Ok(changes) => {
+                let total = changes.len();
+                let mut undone = 0;
+                for journal::Undone { entry, result } in changes {
+                    match result {
+                        Ok(()) => {
+                            output.info(format_args!("Restored {:?}", entry.original));
+                            undone += 1;
+                        }
+                        Err(err) => output.error(format_args!(
+                            "Couldn't undo {} of {:?}\nReason: {}",
+                            entry.action, entry.original, err
+                        )),
+                    }
+                }
+                output.info(format_args!("Undid {} of {} changes", undone, total));
+                if undone < total {
+                    Status::PartialFailure
+                } else {
+                    Status::NoDuplicates
+                }
+            }
+            Err(err) => {
+                output.error(format_args!(
+                    "Failed reading journal {:?}: {}",
+                    undo.journal, err
+                ));
+                Status::Fatal
             }
         };
-        let hash = hasher.hash_image(&image_obj);
-        let mut hash_num: u64 = 0;
-        for (idx, byte) in hash.as_bytes().iter().enumerate() {
-            hash_num |= (*byte as u64) << (8 * idx);
-        }
-
-        // Get the vector (or make a new one if not added) and add a new ImageProperties to it
-        let img_prop = ImageProperties {
-            path: image.clone(),
-            file_size: match fs::metadata(image) {
-                Ok(md) => md.len(),
-                Err(_) => 0,
-            },
+    }
+
+    // Every directory and every image in them share this pool, so it caps the whole run
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build_global()
+    {
+        output.error(format_args!("Failed setting up {} jobs: {}", args.jobs, e));
+        return Status::Fatal;
+    }
+
+    let scanner = match (parse_patterns(&args.include), parse_patterns(&args.exclude)) {
+        (Ok(include), Ok(exclude)) => Scanner {
+            recursive: args.recursive,
+            include,
+            exclude,
+            formats: args.formats.clone(),
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            output.error(e);
+            return Status::Fatal;
+        }
+    };
+
+    if args.hash_size < 2 {
+        output.error("--hash-size must be at least 2");
+        return Status::Fatal;
+    }
+    let options = DedupOptions {
+        scanner,
+        hash: HashOptions {
+            algorithm: args.hash_alg,
+            size: args.hash_size,
+            filter: args.resize_filter,
+            rotation_invariant: args.rotation_invariant,
+            exif_orientation: !args.ignore_exif_orientation,
+            trim_borders: args.trim_borders,
+            detect_crops: args.detect_crops,
+        },
+        threshold: args.threshold,
+        keep: args.keep,
+    };
+
+    if let Some(Command::Index(index)) = &args.command {
+        return run_index(&index.command, options, args.no_cache, &output);
+    }
+
+    if let Some(Command::Watch(watch)) = &args.command {
+        return run_watch(watch, &args, options, &output);
+    }
+
+    let check = match &args.command {
+        Some(Command::Check(check)) => Some(check),
+        _ => None,
+    };
+    let directories = check.map_or(&args.directories, |check| &check.directories);
+    if directories.is_empty() {
+        output.error("Missing argument: directory. Try '--help' for more info.");
+        return Status::Fatal;
+    }
+    if check.is_some() && args.cross {
+        output.error("--cross doesn't apply to check, images are only compared to the reference");
+        return Status::Fatal;
+    }
+    // An index is only read once, here, and stands in for the directory it's an index of
+    let reference_index = match check.map(|check| Path::new(&check.against)) {
+        Some(against) if against.is_file() => match Index::open(against.to_path_buf()) {
+            Ok(index) => Some(index),
+            Err(err) => {
+                output.error(format_args!("Failed reading index {:?}: {}", against, err));
+                return Status::Fatal;
+            }
+        },
+        Some(against) if !against.is_dir() => {
+            output.error(format_args!(
+                "Can't check against {:?}, it isn't a directory or an index",
+                against
+            ));
+            return Status::Fatal;
+        }
+        _ => None,
+    };
+
+    if args.interactive && args.report.is_some() {
+        output.error("--interactive and --report can't be used together, both need the terminal");
+        return Status::Fatal;
+    }
+    if args.interactive && !(io::stdin().is_tty() && io::stdout().is_tty()) {
+        output.error("--interactive needs a terminal to run in");
+        return Status::Fatal;
+    }
+
+    let roots: Vec<&Path> = directories.iter().map(Path::new).collect();
+    let output_dir = args.output_dir.as_ref().map(Path::new);
+    if let Some(output_dir) = output_dir {
+        // Duplicates put in the reference would be found in it by the next check
+        let mut scanned = roots.clone();
+        match &reference_index {
+            Some(index) => scanned.push(index.root()),
+            None => scanned.extend(check.map(|check| Path::new(&check.against))),
+        }
+        if let Err(e) = layout::check_output_dir(output_dir, &scanned, &options.scanner) {
+            output.error(e);
+            return Status::Fatal;
+        }
+    }
+
+    // Queries are hashed the way the index was
+    let hash_options = reference_index
+        .as_ref()
+        .map_or(&options.hash, Index::options);
+    let cache = open_cache(args.no_cache, hash_options, &output);
+
+    // Nothing else is printed while the progress bar is up, it would be drawn over
+    let progress = output.scan_progress();
+    let Duplicates { groups, skipped } = if let Some(check) = check {
+        let (found, reference) = match &reference_index {
+            Some(index) => (
+                find_matches_in_index(&roots, index, &options, cache.as_ref(), &progress),
+                index.root(),
+            ),
+            None => {
+                let reference = Path::new(&check.against);
+                let found = find_matches(&roots, reference, &options, cache.as_ref(), &progress);
+                (found, reference)
+            }
         };
+        progress.finish();
+        if found.groups.is_empty() {
+            output.info(format_args!(
+                "None of the images in {:?} are in {:?}",
+                directories, reference
+            ));
+        }
+        found
+    } else if args.cross {
+        let found = find_duplicates(&roots, &options, cache.as_ref(), &progress);
+        progress.finish();
+        if found.groups.is_empty() {
+            output.info(format_args!(
+                "No duplicates found across {:?}",
+                args.directories
+            ));
+        }
+        found
+    } else {
+        let found: Vec<_> = roots
+            .par_iter()
+            .map(|dir| find_duplicates(&[dir], &options, cache.as_ref(), &progress))
+            .collect();
+        progress.finish();
+        let mut all = Duplicates::default();
+        for (dir, found) in roots.iter().zip(found) {
+            if found.groups.is_empty() {
+                output.info(format_args!("No duplicates found in {:?}", dir));
+            }
+            all.groups.extend(found.groups);
+            all.skipped.extend(found.skipped);
+        }
+        all
+    };
+    let mut status = if groups.is_empty() {
+        Status::NoDuplicates
+    } else {
+        Status::DuplicatesFound
+    };
+    if !skipped.is_empty() {
+        status = Status::PartialFailure;
+    }
 
-        // Gets an Entry from the hash map, which is an enum that can be: OccupiedEntry, VacantEntry.
-        // Adds a new vector if it's a VacantEntry.
-        // https://doc.rust-lang.org/std/collections/hash_map/enum.Entry.html#method.or_default
-        hashes.entry(hash_num).or_default().push(img_prop);
-    }
-
-    let duplicate_path = owned_dir + "/duplicates";
-    // `duplicate_path` *will* be modified within the for_each loops below, beware!
-    let mut duplicate_path = PathBuf::from(&duplicate_path);
-    fs::create_dir_all(&duplicate_path)?;
-    hashes
-        .values()
-        .filter(|entries| entries.len() > 1)
-        .for_each(|entries| {
-            entries.iter().for_each(|e| {
-                // Modify `duplicate_path` to move the file into the `duplicates` directory
-                if let Some(name) = &e.path.file_name() {
-                    duplicate_path.push(name);
-                    match fs::rename(&e.path, &duplicate_path) {
-                        Ok(_) => {}
-                        Err(e) => eprintln!("Failed moving file: {:?}\nReason: {}", name, e),
-                    };
-                    duplicate_path.pop();
-                }
-            })
+    // Saved before anything gets moved, moved images are pruned from it on the next run
+    save_cache(cache, &output);
+
+    let groups = if args.interactive && !groups.is_empty() {
+        match review::review(groups) {
+            Ok(Some(groups)) => groups,
+            Ok(None) => {
+                output.info("Quit the review, no files were touched");
+                report_skipped(&skipped, &output);
+                return status;
+            }
+            Err(err) => {
+                output.error(format_args!(
+                    "Failed running the interactive review: {}",
+                    err
+                ));
+                return Status::Fatal;
+            }
+        }
+    } else {
+        groups
+    };
+
+    // Nothing to undo if nothing gets changed
+    let journal = if args.dry_run || args.action == Action::None {
+        None
+    } else {
+        match &args.journal {
+            Some(path) => Some(Journal::new(PathBuf::from(path))),
+            None => Journal::default_path().map(Journal::new_file),
+        }
+    };
+    let mut executor =
+        Executor::new(&groups, args.action, args.layout, output_dir).dry_run(args.dry_run);
+    if let Some(journal) = journal {
+        executor = executor.with_journal(journal);
+    }
+
+    if let Some(format) = args.report {
+        if let Err(err) = report::write_report(
+            io::stdout().lock(),
+            &groups,
+            args.action,
+            executor.plan(),
+            format,
+        ) {
+            output.error(format_args!("Failed writing report: {}", err));
+            status = Status::PartialFailure;
+        }
+    }
+    // Has to happen before any image is moved, thumbnails are made from the images themselves
+    if let Some(path) = &args.html_report {
+        let written = File::create(path).and_then(|file| {
+            html::write_html_report(
+                BufWriter::new(file),
+                &groups,
+                args.action,
+                executor.plan(),
+                &options.hash,
+            )
+        });
+        match written {
+            Ok(_) => {
+                output.info(format_args!("Wrote HTML report to {:?}", path));
+            }
+            Err(err) => {
+                output.error(format_args!(
+                    "Failed writing HTML report {:?}: {}",
+                    path, err
+                ));
+                status = Status::PartialFailure;
+            }
+        }
+    }
+
+    if apply_actions(&mut executor, &groups, args.dry_run, &output) > 0 {
+        status = Status::PartialFailure;
+    }
+
+    if let Some(journal) = executor.journal().filter(|journal| !journal.is_empty()) {
+        // Still worth knowing about when the report owns stdout
+        output.notice(format_args!(
+            "Changes were recorded in {:?}, undo them with `image-dedup-cli undo {}`",
+            journal.path(),
+            journal.path().display()
+        ));
+    }
+
+    report_skipped(&skipped, &output);
+    status
+}
+
+/// The hash cache for `options`, unless it's turned off.
+fn open_cache(no_cache: bool, options: &HashOptions, output: &Output) -> Option<Mutex<HashCache>> {
+    if no_cache {
+        return None;
+    }
+    HashCache::default_path(options).map(|path| {
+        output.detail(format_args!("Using hash cache {:?}", path));
+        // It's only a cache, starting over is always an option
+        let cache = HashCache::open(path.clone()).unwrap_or_else(|err| {
+            output.error(format_args!(
+                "Ignoring unreadable cache {:?}, original error: {}",
+                path, err
+            ));
+            HashCache::new(path)
         });
+        Mutex::new(cache)
+    })
+}
 
-    // If we didn't find any duplicates, don't leave the empty directory behind
-    if let Ok(d) = fs::read_dir(&duplicate_path) {
-        if d.count() == 0 {
-            match fs::remove_dir(&duplicate_path) {
-                Ok(_) => println!(
-                    "No duplicates found in {:?}",
-                    duplicate_path.parent().unwrap_or_else(|| Path::new(""))
-                ),
-                Err(e) => eprintln!(
-                    "Failed removing duplicate_path: {:?}\nReason: {}",
-                    &duplicate_path, e
-                ),
+fn save_cache(cache: Option<Mutex<HashCache>>, output: &Output) {
+    if let Some(cache) = cache {
+        if let Err(err) = cache.into_inner().unwrap().save() {
+            output.error(format_args!("Failed saving hash cache: {}", err));
+        }
+    }
+}
+
+/// Runs one of the `index` subcommands, with `options` coming from the arguments before `index`.
+fn run_index(
+    command: &IndexCommand,
+    options: DedupOptions,
+    no_cache: bool,
+    output: &Output,
+) -> Status {
+    let mut index = match command {
+        IndexCommand::Build(build) => {
+            match Index::new(
+                PathBuf::from(&build.index),
+                Path::new(&build.collection),
+                options.scanner,
+                options.hash,
+            ) {
+                Ok(index) => index,
+                Err(err) => {
+                    output.error(format_args!("Can't index {:?}: {}", build.collection, err));
+                    return Status::Fatal;
+                }
             }
         }
+        IndexCommand::Update(UpdateArgs { index: path })
+        | IndexCommand::Query(QueryArgs { index: path, .. }) => {
+            match Index::open(PathBuf::from(path)) {
+                Ok(index) => index,
+                Err(err) => {
+                    output.error(format_args!("Failed reading index {:?}: {}", path, err));
+                    return Status::Fatal;
+                }
+            }
+        }
+    };
+
+    if let IndexCommand::Query(query) = command {
+        if query.images.is_empty() {
+            output.error("Missing argument: images. Try '--help' for more info.");
+            return Status::Fatal;
+        }
+        let mut status = Status::NoDuplicates;
+        for image in &query.images {
+            match index.query(Path::new(image), options.threshold) {
+                Ok(matches) if matches.is_empty() => output.info(format_args!(
+                    "Nothing in {:?} is similar to {:?}",
+                    index.root(),
+                    image
+                )),
+                Ok(matches) => {
+                    for (similar, distance) in matches {
+                        output.info(format_args!(
+                            "{:?} is similar to {:?} ({} bits apart)",
+                            similar, image, distance
+                        ));
+                    }
+                    status = status.max(Status::DuplicatesFound);
+                }
+                Err(err) => {
+                    output.error(err);
+                    status = Status::PartialFailure;
+                }
+            }
+        }
+        return status;
+    }
+
+    // Updates use the hashing options the index was built with, whatever was given this time
+    let cache = open_cache(no_cache, index.options(), output);
+    let progress = output.scan_progress();
+    let updated = index.update(cache.as_ref(), &progress);
+    progress.finish();
+    save_cache(cache, output);
+    let updated = match updated {
+        Ok(updated) => updated,
+        Err(err) => {
+            output.error(format_args!("Can't update {:?}: {}", index.path(), err));
+            return Status::Fatal;
+        }
+    };
+    if let Err(err) = index.save() {
+        output.error(format_args!(
+            "Failed writing index {:?}: {}",
+            index.path(),
+            err
+        ));
+        return Status::Fatal;
     }
 
-    // Clippy complains if I use an explicit return, but I hate implicit returns >:(
-    Ok(())
+    output.info(format_args!(
+        "Indexed {} images in {:?}: {} added, {} changed, {} removed, {} unchanged",
+        index.len(),
+        index.root(),
+        updated.added,
+        updated.changed,
+        updated.removed,
+        updated.unchanged
+    ));
+    report_skipped(&updated.skipped, output);
+    if updated.skipped.is_empty() {
+        Status::NoDuplicates
+    } else {
+        Status::PartialFailure
+    }
 }
 
-fn main() {
-    let args: Args = argh::from_env();
+/// Runs `watch` until the watcher stops, with `options` coming from the arguments before `watch`.
+fn run_watch(
+    watch_args: &WatchArgs,
+    args: &Args,
+    options: DedupOptions,
+    output: &Output,
+) -> Status {
+    let unsupported = [
+        (args.cross, "--cross"),
+        (args.interactive, "--interactive"),
+        (args.report.is_some(), "--report"),
+        (args.html_report.is_some(), "--html-report"),
+        // Every duplicate is a group of its own, they'd all get the same number
+        (args.layout == Layout::Group, "--layout group"),
+    ];
+    if let Some((_, option)) = unsupported.iter().find(|(given, _)| *given) {
+        output.error(format_args!("{} doesn't apply to watch", option));
+        return Status::Fatal;
+    }
+    let directory = Path::new(&watch_args.directory);
+    let output_dir = args.output_dir.as_ref().map(Path::new);
+    if let Some(output_dir) = output_dir {
+        if let Err(e) = layout::check_output_dir(output_dir, &[directory], &options.scanner) {
+            output.error(e);
+            return Status::Fatal;
+        }
+    }
+
+    let cache = open_cache(args.no_cache, &options.hash, output);
+    let progress = output.scan_progress();
+    let started = Watch::new(directory, options, cache.as_ref(), &progress);
+    progress.finish();
+    // Only used for what's already there, new images are hashed once and never again
+    save_cache(cache, output);
+    let mut watch = match started {
+        Ok((watch, skipped)) => {
+            report_skipped(&skipped, output);
+            watch
+        }
+        Err(err) => {
+            output.error(format_args!("Can't watch {:?}: {}", directory, err));
+            return Status::Fatal;
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let watcher = notify::watcher(tx, Duration::from_secs(watch_args.settle)).and_then(|mut w| {
+        let mode = if args.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        w.watch(watch.root(), mode).map(|_| w)
+    });
+    // Dropping the watcher stops it
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            output.error(format_args!("Failed watching {:?}: {}", directory, err));
+            return Status::Fatal;
+        }
+    };
+    output.info(format_args!(
+        "Watching {:?} with {} images in it, press Ctrl-C to stop",
+        watch.root(),
+        watch.len()
+    ));
 
-    if args.directories.is_empty() {
-        eprintln!("Missing argument: directory. Try '--help' for more info.")
+    // The same journal is appended to by every executor, so the whole session undoes at once. The
+    // default one only gets its name once the first change claims it
+    let mut journal_path = if args.dry_run || args.action == Action::None {
+        None
     } else {
-        args.directories
-            .par_iter()
-            .map(|dir| find_duplicates(dir))
-            .filter_map(Result::err)
-            .for_each(|err| eprintln!("{:?}", err));
+        args.journal
+            .clone()
+            .map(PathBuf::from)
+            .or_else(Journal::default_path)
+    };
+    let mut noticed_journal = false;
+    for event in rx {
+        let found = match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => watch.arrived(&path),
+            DebouncedEvent::Rename(from, to) => {
+                watch.removed(&from);
+                watch.arrived(&to)
+            }
+            DebouncedEvent::Remove(path) => {
+                watch.removed(&path);
+                continue;
+            }
+            DebouncedEvent::Rescan => {
+                output.detail(format_args!(
+                    "Events were missed, rescanning {:?}",
+                    watch.root()
+                ));
+                Duplicates {
+                    groups: Vec::new(),
+                    skipped: watch.rescan(None, &()),
+                }
+            }
+            DebouncedEvent::Error(err, path) => {
+                output.error(match path {
+                    Some(path) => format!("Error watching {:?}: {}", path, err),
+                    None => format!("Error watching {:?}: {}", watch.root(), err),
+                });
+                continue;
+            }
+            // The debounced Create or Write follows once the file settles
+            _ => continue,
+        };
+        for err in &found.skipped {
+            output.error(err);
+        }
+
+        let mut executor = Executor::new(&found.groups, args.action, args.layout, output_dir)
+            .dry_run(args.dry_run);
+        if let Some(path) = &journal_path {
+            let journal = match args.journal.is_none() && !noticed_journal {
+                true => Journal::new_file(path.clone()),
+                false => Journal::new(path.clone()),
+            };
+            executor = executor.with_journal(journal);
+        }
+        apply_actions(&mut executor, &found.groups, args.dry_run, output);
+        if let Some(journal) = executor.journal().filter(|journal| !journal.is_empty()) {
+            if !noticed_journal {
+                output.notice(format_args!(
+                    "Changes are recorded in {:?}, undo them with `image-dedup-cli undo {}`",
+                    journal.path(),
+                    journal.path().display()
+                ));
+                journal_path = Some(journal.path().to_path_buf());
+                noticed_journal = true;
+            }
+        }
+    }
+
+    output.error("Stopped watching, the watcher went away");
+    Status::Fatal
+}
+
+/// Lists every file that was skipped, at the end so it isn't lost between everything else.
+fn report_skipped(skipped: &[Error], output: &Output) {
+    match skipped.len() {
+        0 => return,
+        1 => output.error("Skipped 1 file that couldn't be checked:"),
+        count => output.error(format_args!(
+            "Skipped {} files that couldn't be checked:",
+            count
+        )),
+    }
+    for err in skipped {
+        output.error(format_args!("  {}", err));
     }
 }
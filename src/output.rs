@@ -0,0 +1,239 @@
+//! Everything the CLI has to say, and how much of it gets said.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crossterm::tty::IsTty;
+use image_dedup_cli::{Error, Progress};
+use indicatif::{HumanDuration, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// How often a progress line is printed when stderr isn't a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// How much the user wants to hear about.
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    /// What happens to every duplicate, and how the scan is coming along.
+    Normal,
+    /// Also every image as it's hashed or skipped, and a summary of the scan.
+    Verbose,
+}
+
+/// Prints messages according to the verbosity, keeping them out of the way of a report on stdout.
+pub struct Output {
+    verbosity: Verbosity,
+    /// Set when a report is written to stdout, anything else printed there would end up in it.
+    report_on_stdout: bool,
+}
+
+impl Output {
+    pub fn new(verbosity: Verbosity, report_on_stdout: bool) -> Output {
+        Output {
+            verbosity,
+            report_on_stdout,
+        }
+    }
+
+    /// What was (or would be) done, left out if quiet or if stdout belongs to a report.
+    pub fn info(&self, msg: impl fmt::Display) {
+        if self.verbosity >= Verbosity::Normal && !self.report_on_stdout {
+            println!("{}", msg);
+        }
+    }
+
+    /// Like `info`, but still worth knowing with a report on stdout, so it goes to stderr then.
+    pub fn notice(&self, msg: impl fmt::Display) {
+        match self.verbosity {
+            Verbosity::Quiet => {}
+            _ if self.report_on_stdout => eprintln!("{}", msg),
+            _ => println!("{}", msg),
+        }
+    }
+
+    /// Only for `--verbose`, on stderr.
+    pub fn detail(&self, msg: impl fmt::Display) {
+        if self.verbosity >= Verbosity::Verbose {
+            eprintln!("{}", msg);
+        }
+    }
+
+    /// Always shown, on stderr.
+    pub fn error(&self, msg: impl fmt::Display) {
+        eprintln!("{}", msg);
+    }
+
+    /// Progress for a scan that's about to start, see `ScanProgress`.
+    pub fn scan_progress(&self) -> ScanProgress {
+        let bar = if self.verbosity >= Verbosity::Normal && io::stderr().is_tty() {
+            let bar = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::stderr());
+            let style = ProgressStyle::default_bar().template(
+                "[{elapsed_precise}] {bar:30} {pos}/{len} images ({per_sec}, {eta} left) {wide_msg}",
+            );
+            bar.set_style(style.expect("progress bar template is valid"));
+            Some(bar)
+        } else {
+            None
+        };
+        ScanProgress {
+            plain: bar.is_none() && self.verbosity >= Verbosity::Normal,
+            bar,
+            verbose: self.verbosity >= Verbosity::Verbose,
+            found: AtomicUsize::new(0),
+            hashed: AtomicUsize::new(0),
+            cached: AtomicUsize::new(0),
+            identical: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            started: Instant::now(),
+            last_line: Mutex::new(Instant::now()),
+            printed_line: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Shows how far along a scan is on stderr: a progress bar on a terminal, otherwise a plain line
+/// every `PLAIN_INTERVAL` so logs don't fill up with redraws.
+pub struct ScanProgress {
+    bar: Option<ProgressBar>,
+    /// Print progress lines, there's no bar but the user still wants to hear about it.
+    plain: bool,
+    verbose: bool,
+    found: AtomicUsize,
+    hashed: AtomicUsize,
+    cached: AtomicUsize,
+    identical: AtomicUsize,
+    failed: AtomicUsize,
+    started: Instant,
+    last_line: Mutex<Instant>,
+    printed_line: AtomicBool,
+}
+
+impl ScanProgress {
+    /// How many of the images found so far were checked one way or another.
+    fn done(&self) -> usize {
+        self.hashed.load(Ordering::Relaxed)
+            + self.cached.load(Ordering::Relaxed)
+            + self.identical.load(Ordering::Relaxed)
+            + self.failed.load(Ordering::Relaxed)
+    }
+
+    fn counts(&self) -> String {
+        format!(
+            "{} hashed, {} from the cache, {} identical copies, {} failed",
+            self.hashed.load(Ordering::Relaxed),
+            self.cached.load(Ordering::Relaxed),
+            self.identical.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Prints `msg` above the bar if there is one, so it isn't drawn over.
+    fn print(&self, msg: impl AsRef<str>) {
+        match &self.bar {
+            Some(bar) => bar.println(msg),
+            None => eprintln!("{}", msg.as_ref()),
+        }
+    }
+
+    /// Moves the bar along by `count` images, or prints a line if it's been a while.
+    fn advance(&self, count: usize) {
+        if let Some(bar) = &self.bar {
+            bar.inc(count as u64);
+            bar.set_message(self.counts());
+        }
+        if !self.plain {
+            return;
+        }
+        // Whichever thread gets here first prints it, the others just carry on
+        let mut last_line = match self.last_line.try_lock() {
+            Ok(last_line) => last_line,
+            Err(_) => return,
+        };
+        if last_line.elapsed() < PLAIN_INTERVAL {
+            return;
+        }
+        *last_line = Instant::now();
+        self.printed_line.store(true, Ordering::Relaxed);
+
+        let done = self.done();
+        let found = self.found.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = done as f64 / elapsed;
+        let left = if rate > 0.0 {
+            format!(
+                ", about {} left",
+                HumanDuration(Duration::from_secs_f64(
+                    found.saturating_sub(done) as f64 / rate
+                ))
+            )
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "Checked {} of {} images ({}), {:.0}/s{}",
+            done,
+            found,
+            self.counts(),
+            rate,
+            left
+        );
+    }
+
+    /// Takes the bar down again, summing the scan up if anyone's interested.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        if self.verbose || self.printed_line.load(Ordering::Relaxed) {
+            eprintln!(
+                "Checked {} images in {} ({})",
+                self.done(),
+                HumanDuration(self.started.elapsed()),
+                self.counts()
+            );
+        }
+    }
+}
+
+impl Progress for ScanProgress {
+    fn found(&self, count: usize) {
+        self.found.fetch_add(count, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc_length(count as u64);
+        }
+    }
+
+    fn hashed(&self, path: &Path, cached: bool) {
+        if cached {
+            self.cached.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.hashed.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.verbose {
+            self.print(if cached {
+                format!("Read {:?} from the cache", path)
+            } else {
+                format!("Hashed {:?}", path)
+            });
+        }
+        self.advance(1);
+    }
+
+    fn identical(&self, count: usize) {
+        self.identical.fetch_add(count, Ordering::Relaxed);
+        self.advance(count);
+    }
+
+    fn failed(&self, error: &Error) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        if self.verbose {
+            self.print(error.to_string());
+        }
+        self.advance(1);
+    }
+}
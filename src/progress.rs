@@ -0,0 +1,26 @@
+//! Following along with `find_duplicates` while it works through a large scan.
+
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Told about every image `find_duplicates` finds and gets through, so progress can be shown.
+///
+/// Every method does nothing by default. Images are hashed on rayon's thread pool, so calls come
+/// from many threads at once and in no particular order.
+pub trait Progress: Sync {
+    /// `count` more images were found, and will be checked.
+    fn found(&self, _count: usize) {}
+
+    /// The image at `path` was hashed, or its hash was read from the cache if `cached`.
+    fn hashed(&self, _path: &Path, _cached: bool) {}
+
+    /// `count` images didn't need hashing at all, being byte for byte copies of one that did.
+    fn identical(&self, _count: usize) {}
+
+    /// An image that was found couldn't be checked after all.
+    fn failed(&self, _error: &Error) {}
+}
+
+/// Doesn't follow along at all.
+impl Progress for () {}
@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::action::Action;
+use crate::layout::Plan;
+use crate::DuplicateGroup;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Machine-readable formats duplicate groups can be reported in.
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!(
+                "unknown report format {:?}, expected json or csv",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+/// A single duplicate group, as it appears in the report.
+struct GroupReport {
+    /// Whether the images are byte-for-byte identical ("exact"), only look alike
+    /// ("perceptual") or one is a crop of the other ("crop").
+    #[serde(rename = "match")]
+    kind: String,
+    hash: String,
+    keeper: String,
+    /// For crop matches, how much of the larger image the crop covers, from 0 to 1.
+    overlap: Option<f64>,
+    images: Vec<ImageReport>,
+}
+
+#[derive(Serialize)]
+/// A single image of a duplicate group, as it appears in the report.
+struct ImageReport {
+    path: String,
+    size: u64,
+    width: u32,
+    height: u32,
+    keep: bool,
+    /// What happens (or would happen) to the image, `None` for the keeper.
+    action: Option<String>,
+    /// Where the image is (or would be) moved or copied to, if anywhere.
+    destination: Option<String>,
+    /// How the kept image is rotated or mirrored compared to this one, `None` for the keeper.
+    transform: Option<String>,
+}
+
+impl GroupReport {
+    fn new(group: &DuplicateGroup, action: Action, plan: &Plan) -> Self {
+        let images = group
+            .images
+            .iter()
+            .enumerate()
+            .map(|(idx, img)| {
+                let keep = idx == group.keeper;
+                ImageReport {
+                    path: img.path.to_string_lossy().into_owned(),
+                    size: img.file_size,
+                    width: img.width,
+                    height: img.height,
+                    keep,
+                    action: if keep { None } else { Some(action.to_string()) },
+                    // A keeper can still be a duplicate in another group, its destination is
+                    // reported there
+                    destination: if keep {
+                        None
+                    } else {
+                        plan.destination(img)
+                            .map(|p| p.to_string_lossy().into_owned())
+                    },
+                    transform: if keep {
+                        None
+                    } else {
+                        Some(group.relation(img).0.to_string())
+                    },
+                }
+            })
+            .collect();
+
+        GroupReport {
+            kind: group.kind.to_string(),
+            hash: group.keeper().hash.to_string(),
+            keeper: group.keeper().path.to_string_lossy().into_owned(),
+            // Window sizes are fractions of 100, anything past 2 decimals is rounding noise
+            overlap: group.overlap().map(|o| (o * 100.0).round() / 100.0),
+            images,
+        }
+    }
+}
+
+/// Writes every group in `groups` to `out` in the given format, along with the `action` planned for
+/// each duplicate and where `plan` puts it.
+///
+/// JSON is a single array of groups, CSV has one row per image with a `group` column tying the
+/// images of a group together.
+pub fn write_report<W: Write>(
+    mut out: W,
+    groups: &[DuplicateGroup],
+    action: Action,
+    plan: &Plan,
+    format: ReportFormat,
+) -> Result<(), io::Error> {
+    let reports: Vec<GroupReport> = groups
+        .iter()
+        .map(|group| GroupReport::new(group, action, plan))
+        .collect();
+
+    match format {
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &reports)?;
+            writeln!(out)?;
+        }
+        ReportFormat::Csv => {
+            writeln!(
+                out,
+                "group,match,hash,path,size,width,height,keep,action,destination,transform,overlap"
+            )?;
+            for (idx, group) in reports.iter().enumerate() {
+                for img in &group.images {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{},{},{},{},{},{},{}",
+                        idx,
+                        group.kind,
+                        group.hash,
+                        csv_field(&img.path),
+                        img.size,
+                        img.width,
+                        img.height,
+                        img.keep,
+                        img.action.as_deref().unwrap_or(""),
+                        csv_field(img.destination.as_deref().unwrap_or("")),
+                        img.transform.as_deref().unwrap_or(""),
+                        group.overlap.map(|o| o.to_string()).unwrap_or_default()
+                    )?;
+                }
+            }
+        }
+    }
+
+    out.flush()
+}
+
+/// Quotes a CSV field if it contains anything that would otherwise break the row apart.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,263 @@
+//! Reviewing duplicate groups one at a time in the terminal before anything is touched.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use image_dedup_cli::{DuplicateGroup, MatchKind, Transform};
+
+const HELP: [&str; 2] = [
+    "up/down: select   enter: keep selected   s: skip group",
+    "a: accept all remaining   b: back   q: quit without changing anything",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What the user decided to do with a group.
+enum Decision {
+    /// Keep the image at this index, the rest get the usual action.
+    Keep(usize),
+    /// Leave every image of the group alone.
+    Skip,
+}
+
+/// Puts the terminal into raw mode on an alternate screen for as long as it's alive.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Screen, io::Error> {
+        terminal::enable_raw_mode()?;
+        // Created before anything else can fail, so raw mode is always switched off again
+        let screen = Screen;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Walks through `groups` one at a time, letting the user pick which image of each to keep or skip
+/// it entirely. Nothing is decided for good until the last group is done, so going back is always
+/// possible.
+///
+/// Returns the groups to act on, with the keepers the user picked and skipped groups left out, or
+/// `None` if the user quit.
+pub fn review(mut groups: Vec<DuplicateGroup>) -> Result<Option<Vec<DuplicateGroup>>, io::Error> {
+    let mut decisions: Vec<Option<Decision>> = vec![None; groups.len()];
+    let mut dates = HashMap::new();
+    let mut current = 0;
+    let mut cursor = groups.first().map(|group| group.keeper).unwrap_or(0);
+
+    {
+        let _screen = Screen::enter()?;
+        while current < groups.len() {
+            let group = &groups[current];
+            let keeper = match decisions[current] {
+                Some(Decision::Keep(idx)) => idx,
+                _ => group.keeper,
+            };
+            draw(group, current, groups.len(), keeper, cursor, &mut dates)?;
+            let previous = current;
+
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+            match key {
+                KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                }
+                | KeyEvent {
+                    code: KeyCode::Char('q') | KeyCode::Esc,
+                    ..
+                } => return Ok(None),
+                KeyEvent {
+                    code: KeyCode::Up | KeyCode::Char('k'),
+                    ..
+                } => cursor = cursor.saturating_sub(1),
+                KeyEvent {
+                    code: KeyCode::Down | KeyCode::Char('j'),
+                    ..
+                } => cursor = (cursor + 1).min(group.images.len() - 1),
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                } => {
+                    decisions[current] = Some(Decision::Keep(cursor));
+                    current += 1;
+                }
+                KeyEvent {
+                    code: KeyCode::Char('s'),
+                    ..
+                } => {
+                    decisions[current] = Some(Decision::Skip);
+                    current += 1;
+                }
+                KeyEvent {
+                    code: KeyCode::Char('a'),
+                    ..
+                } => {
+                    // Whatever was already decided for later groups (after going back) stays
+                    for (decision, group) in decisions[current..].iter_mut().zip(&groups[current..])
+                    {
+                        decision.get_or_insert(Decision::Keep(group.keeper));
+                    }
+                    current = groups.len();
+                }
+                KeyEvent {
+                    code: KeyCode::Char('b'),
+                    ..
+                } => current = current.saturating_sub(1),
+                _ => continue,
+            }
+
+            // The cursor starts out on whichever image is kept in the group we land on
+            if let Some(group) = groups.get(current).filter(|_| current != previous) {
+                cursor = match decisions[current] {
+                    Some(Decision::Keep(idx)) => idx,
+                    _ => group.keeper,
+                };
+            }
+        }
+    }
+
+    let mut reviewed = Vec::new();
+    for (mut group, decision) in groups.drain(..).zip(decisions) {
+        if let Some(Decision::Keep(idx)) = decision {
+            group.keeper = idx;
+            reviewed.push(group);
+        }
+    }
+    Ok(Some(reviewed))
+}
+
+/// Draws `group`, number `idx` out of `total`, with `keeper` marked as kept and the image at
+/// `cursor` highlighted.
+fn draw(
+    group: &DuplicateGroup,
+    idx: usize,
+    total: usize,
+    keeper: usize,
+    cursor: usize,
+    dates: &mut HashMap<PathBuf, Option<String>>,
+) -> Result<(), io::Error> {
+    // Some terminals (and pseudo terminals) don't report a size at all
+    let width = match terminal::size() {
+        Ok((width, _)) if width > 0 => width as usize,
+        _ => 80,
+    };
+    let mut out = io::stdout();
+    queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+
+    let title = match group.overlap() {
+        Some(overlap) => format!(
+            "Group {} of {} ({} match, {:.0}% overlap)",
+            idx + 1,
+            total,
+            group.kind,
+            overlap * 100.0
+        ),
+        None => format!("Group {} of {} ({} match)", idx + 1, total, group.kind),
+    };
+    let mut lines = vec![title, String::new()];
+    for (img_idx, img) in group.images.iter().enumerate() {
+        let date = dates
+            .entry(img.path.clone())
+            .or_insert_with(|| exif_date(&img.path));
+        lines.push(format!(
+            "{} {} {}",
+            if img_idx == cursor { ">" } else { " " },
+            if img_idx == keeper {
+                "[keep]"
+            } else {
+                "      "
+            },
+            img.path.display()
+        ));
+        lines.push(format!(
+            "         {}, {}x{}, taken {}, {}",
+            human_size(img.file_size),
+            img.width,
+            img.height,
+            date.as_deref().unwrap_or("unknown"),
+            distance(group, img_idx, keeper)
+        ));
+    }
+    lines.push(String::new());
+    lines.extend(HELP.iter().map(|line| line.to_string()));
+
+    for (line_idx, line) in lines.iter().enumerate() {
+        // Lines that wrap would push everything below them around
+        let line: String = line.chars().take(width).collect();
+        let highlighted = line_idx >= 2 && (line_idx - 2) / 2 == cursor;
+        if highlighted {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        queue!(
+            out,
+            Print(line),
+            SetAttribute(Attribute::Reset),
+            Print("\r\n")
+        )?;
+    }
+    out.flush()
+}
+
+/// How far the hash of image `img` of `group` is from that of the image selected to be kept, as
+/// shown next to each image.
+fn distance(group: &DuplicateGroup, img: usize, keeper: usize) -> String {
+    // Crop groups only have the two images, how far apart they are doesn't depend on which is kept
+    let (transform, bits) = if img == keeper {
+        (Transform::Identity, 0)
+    } else if keeper == group.keeper || group.kind == MatchKind::Crop {
+        group.relation(&group.images[img])
+    } else {
+        group.images[keeper].closest_transform(&group.images[img])
+    };
+    let distance = match bits {
+        0 => "same hash as the kept image".to_string(),
+        1 => "1 bit from the kept image".to_string(),
+        bits => format!("{} bits from the kept image", bits),
+    };
+    match transform {
+        Transform::Identity => distance,
+        transform => format!("{} after {}", distance, transform),
+    }
+}
+
+/// When the photo was taken according to its EXIF data, if it has any.
+fn exif_date(path: &Path) -> Option<String> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .iter()
+        .find_map(|tag| exif.get_field(*tag, exif::In::PRIMARY))
+        .map(|field| field.display_value().to_string())
+}
+
+/// `bytes` in the largest unit that keeps it at least 1, e.g. `1.4 MiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glob::Pattern;
+use image::ImageFormat;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::error::Error;
+
+/// Every format that can be both detected and decoded, along with the names it's known by.
+const SUPPORTED_FORMATS: [(&[&str], ImageFormat); 10] = [
+    (&["png"], ImageFormat::Png),
+    (&["jpeg", "jpg"], ImageFormat::Jpeg),
+    (&["gif"], ImageFormat::Gif),
+    (&["webp"], ImageFormat::WebP),
+    (&["bmp"], ImageFormat::Bmp),
+    (&["tiff", "tif"], ImageFormat::Tiff),
+    (&["ico"], ImageFormat::Ico),
+    (&["pnm", "pbm", "pgm", "ppm", "pam"], ImageFormat::Pnm),
+    (&["hdr"], ImageFormat::Hdr),
+    (&["farbfeld", "ff"], ImageFormat::Farbfeld),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The image formats to look for, parsed from a comma separated list of format names.
+pub struct Formats(Vec<ImageFormat>);
+
+impl Default for Formats {
+    /// Every supported format.
+    fn default() -> Self {
+        Formats(
+            SUPPORTED_FORMATS
+                .iter()
+                .map(|(_, format)| *format)
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Formats {
+    /// The formats' main names, comma separated so it parses back into the same formats.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = SUPPORTED_FORMATS
+            .iter()
+            .filter(|(_, format)| self.0.contains(format))
+            .map(|(names, _)| names[0])
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl FromStr for Formats {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formats = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let format = SUPPORTED_FORMATS
+                .iter()
+                .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+                .map(|(_, format)| *format)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown image format {:?}, expected any of: png, jpeg, gif, webp, bmp, tiff, ico, pnm, hdr, farbfeld",
+                        name
+                    )
+                })?;
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+
+        if formats.is_empty() {
+            return Err("expected at least one image format".to_owned());
+        }
+        Ok(Formats(formats))
+    }
+}
+
+/// How much of a file is read to work out its format, enough to get past the comments at the
+/// start of a PNM header.
+const SNIFF_LEN: u64 = 512;
+
+/// Works out the format of the file at `path` from its first few bytes, ignoring its extension.
+/// Files that aren't images in any format `image` knows of give `None`.
+pub fn sniff_format(path: &Path) -> Result<Option<ImageFormat>, io::Error> {
+    let mut header = Vec::with_capacity(SNIFF_LEN as usize);
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut header)?;
+
+    Ok(match image::guess_format(&header) {
+        // `image` takes any RIFF container to be WebP, so make sure it actually is
+        Ok(ImageFormat::WebP) if header.get(8..12) != Some(b"WEBP") => None,
+        // Two letter signatures start plenty of text files too, so those need a proper header
+        Ok(ImageFormat::Pnm) if !is_pnm_header(&header) => None,
+        Ok(ImageFormat::Bmp) if !is_bmp_header(&header) => None,
+        Ok(ImageFormat::Hdr) if !matches!(header.get(10), Some(b'\n')) => None,
+        Ok(format) => Some(format),
+        Err(_) => None,
+    })
+}
+
+/// Whether `header` starts like a PNM image: `P1` to `P6` followed by a number (the width), or
+/// `P7` followed by a PAM header line, with any comments in between.
+fn is_pnm_header(header: &[u8]) -> bool {
+    const PAM_KEYWORDS: [&[u8]; 6] = [
+        b"WIDTH",
+        b"HEIGHT",
+        b"DEPTH",
+        b"MAXVAL",
+        b"TUPLTYPE",
+        b"ENDHDR",
+    ];
+
+    let mut rest = match header.get(2..) {
+        Some(rest) if rest.first().is_some_and(u8::is_ascii_whitespace) => rest,
+        _ => return false,
+    };
+    loop {
+        let start = rest.iter().position(|b| !b.is_ascii_whitespace());
+        rest = &rest[start.unwrap_or(rest.len())..];
+        if rest.first() != Some(&b'#') {
+            break;
+        }
+        match rest.iter().position(|&b| b == b'\n') {
+            Some(end) => rest = &rest[end..],
+            // Comments this long are odd, but the decoder can sort that out
+            None => return true,
+        }
+    }
+
+    if header[1] == b'7' {
+        PAM_KEYWORDS.iter().any(|keyword| rest.starts_with(keyword))
+    } else {
+        rest.first().is_some_and(u8::is_ascii_digit)
+    }
+}
+
+/// Whether `header` starts like a BMP image: `BM`, then the file header, then a bitmap info header
+/// of one of the sizes the format has had over the years.
+fn is_bmp_header(header: &[u8]) -> bool {
+    match header.get(14..18) {
+        Some(size) => {
+            let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+            matches!(size, 12 | 16 | 40 | 52 | 56 | 64 | 108 | 124)
+        }
+        None => false,
+    }
+}
+
+/// Name of the directory duplicates are moved into, it's never scanned itself.
+pub const DUPLICATES_DIR: &str = "duplicates";
+
+#[derive(Clone, Debug, Default)]
+/// Finds the images under a directory that are considered for deduplication.
+pub struct Scanner {
+    /// Walk into subdirectories instead of only looking at the top level.
+    pub recursive: bool,
+    /// If non-empty, only files matching at least one of these are scanned.
+    pub include: Vec<Pattern>,
+    /// Files and directories matching any of these are skipped.
+    pub exclude: Vec<Pattern>,
+    /// Only images in one of these formats are scanned.
+    pub formats: Formats,
+}
+
+impl Scanner {
+    /// Patterns without a `/` are matched against the file name at any depth, everything else is
+    /// matched against the path relative to the scanned directory.
+    fn matches(patterns: &[Pattern], relative: &Path) -> bool {
+        patterns.iter().any(|pattern| {
+            if pattern.as_str().contains('/') {
+                pattern.matches_path(relative)
+            } else {
+                relative
+                    .file_name()
+                    .map(|name| pattern.matches(&name.to_string_lossy()))
+                    .unwrap_or(false)
+            }
+        })
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        Self::matches(&self.exclude, relative)
+    }
+
+    fn is_included(&self, relative: &Path) -> bool {
+        self.include.is_empty() || Self::matches(&self.include, relative)
+    }
+
+    /// Whether files in the directory at `relative` (to the scanned directory) would be scanned.
+    pub fn scans_dir(&self, relative: &Path) -> bool {
+        if relative.as_os_str().is_empty() {
+            return true;
+        }
+        if !self.recursive {
+            return false;
+        }
+        // Skipping a directory skips everything under it too
+        !relative.ancestors().any(|dir| {
+            !dir.as_os_str().is_empty()
+                && (dir == Path::new(DUPLICATES_DIR) || self.is_excluded(dir))
+        })
+    }
+
+    /// Finds every image under `directory` that should be checked for duplicates.
+    ///
+    /// Images are recognised by their contents rather than their extension, so misnamed or
+    /// extensionless images are found as well.
+    ///
+    /// Symlinked directories are followed, but loops are detected and skipped, as are symlinked files
+    /// and any file that was already reached through another path or is a hard link to one that
+    /// was, since those aren't real copies.
+    ///
+    /// Entries that can't be read are skipped and returned alongside the images.
+    pub fn find_images(&self, directory: &Path) -> (Vec<PathBuf>, Vec<Error>) {
+        let max_depth = if self.recursive { usize::MAX } else { 1 };
+        let walker = WalkDir::new(directory)
+            .follow_links(true)
+            .max_depth(max_depth)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_entry(|entry| !is_skipped_dir(directory, entry, self));
+
+        let mut seen = HashSet::new();
+        let mut images = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                // A link back up the tree, everything it leads to is found the other way anyway
+                Err(e) if e.loop_ancestor().is_some() => continue,
+                Err(e) => {
+                    let path = e.path().unwrap_or(directory).to_path_buf();
+                    let message = e.to_string();
+                    let source = e
+                        .into_io_error()
+                        .unwrap_or_else(|| io::Error::other(message));
+                    skipped.push(Error::Io { path, source });
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() || entry.path_is_symlink() {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(directory).unwrap_or(entry.path());
+            if self.is_excluded(relative) || !self.is_included(relative) {
+                continue;
+            }
+            match sniff_format(entry.path()) {
+                Ok(Some(format)) if self.formats.0.contains(&format) => {}
+                Ok(_) => continue,
+                Err(source) => {
+                    skipped.push(Error::Io {
+                        path: entry.into_path(),
+                        source,
+                    });
+                    continue;
+                }
+            }
+
+            // Two routes to the same file would look like duplicates, and so would the hard links
+            // an earlier run left behind
+            if let Ok(id) = file_id(entry.path()) {
+                if !seen.insert(id) {
+                    continue;
+                }
+            }
+            images.push(entry.into_path());
+        }
+
+        (images, skipped)
+    }
+
+    /// Whether `find_images(directory)` would find the file at `path`, for checking files one at a
+    /// time as they show up.
+    pub fn accepts(&self, directory: &Path, path: &Path) -> Result<bool, io::Error> {
+        let relative = path.strip_prefix(directory).unwrap_or(path);
+        let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+        if !fs::symlink_metadata(path)?.is_file()
+            || !self.scans_dir(parent)
+            || self.is_excluded(relative)
+            || !self.is_included(relative)
+        {
+            return Ok(false);
+        }
+        Ok(matches!(sniff_format(path)?, Some(format) if self.formats.0.contains(&format)))
+    }
+}
+
+#[cfg(unix)]
+/// What tells files apart whatever name they're reached by, hard links to a file included.
+pub(crate) type FileId = (u64, u64);
+
+#[cfg(not(unix))]
+pub(crate) type FileId = PathBuf;
+
+#[cfg(unix)]
+/// The identity of the file at `path`, following symlinks.
+pub(crate) fn file_id(path: &Path) -> Result<FileId, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_id(path: &Path) -> Result<FileId, io::Error> {
+    fs::canonicalize(path)
+}
+
+/// Parses every glob in `patterns`, failing on the first invalid one.
+pub fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>, Error> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|source| Error::Pattern {
+                pattern: pattern.clone(),
+                source,
+            })
+        })
+        .collect()
+}
+
+/// Whether `entry` is a directory that shouldn't be descended into.
+fn is_skipped_dir(root: &Path, entry: &DirEntry, scanner: &Scanner) -> bool {
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+
+    let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+    relative == Path::new(DUPLICATES_DIR) || scanner.is_excluded(relative)
+}
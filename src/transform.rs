@@ -0,0 +1,97 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::DynamicImage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// One of the 8 ways an image can be rotated and mirrored without distorting it.
+pub enum Transform {
+    Identity,
+    /// Rotated 90° clockwise.
+    Rotate90,
+    Rotate180,
+    /// Rotated 90° counter-clockwise.
+    Rotate270,
+    /// Mirrored left to right.
+    FlipHorizontal,
+    /// Mirrored top to bottom.
+    FlipVertical,
+    /// Mirrored along the diagonal from the top left corner to the bottom right one.
+    Transpose,
+    /// Mirrored along the diagonal from the top right corner to the bottom left one.
+    Transverse,
+}
+
+impl Transform {
+    /// Every transform, starting with `Identity`. Hashes of an image's orientations are kept in
+    /// this order.
+    pub const ALL: [Transform; 8] = [
+        Transform::Identity,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::FlipHorizontal,
+        Transform::FlipVertical,
+        Transform::Transpose,
+        Transform::Transverse,
+    ];
+
+    /// The transform that turns an image upright according to its EXIF `Orientation`, which
+    /// numbers the transforms 1 to 8.
+    pub fn from_exif_orientation(orientation: u32) -> Option<Transform> {
+        match orientation {
+            1 => Some(Transform::Identity),
+            2 => Some(Transform::FlipHorizontal),
+            3 => Some(Transform::Rotate180),
+            4 => Some(Transform::FlipVertical),
+            5 => Some(Transform::Transpose),
+            6 => Some(Transform::Rotate90),
+            7 => Some(Transform::Transverse),
+            8 => Some(Transform::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Transform::Identity => image.clone(),
+            Transform::Rotate90 => image.rotate90(),
+            Transform::Rotate180 => image.rotate180(),
+            Transform::Rotate270 => image.rotate270(),
+            Transform::FlipHorizontal => image.fliph(),
+            Transform::FlipVertical => image.flipv(),
+            Transform::Transpose => image.rotate90().fliph(),
+            Transform::Transverse => image.rotate90().flipv(),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transform::Identity => "none",
+            Transform::Rotate90 => "rotate-90",
+            Transform::Rotate180 => "rotate-180",
+            Transform::Rotate270 => "rotate-270",
+            Transform::FlipHorizontal => "flip-horizontal",
+            Transform::FlipVertical => "flip-vertical",
+            Transform::Transpose => "transpose",
+            Transform::Transverse => "transverse",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How the image at `path` has to be transformed to be upright, according to its EXIF orientation.
+/// Images without one, or whose EXIF data can't be read, are taken to be upright already.
+pub fn exif_orientation(path: &Path) -> Transform {
+    let orientation = || {
+        let mut reader = BufReader::new(File::open(path).ok()?);
+        let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+        let field = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?;
+        Transform::from_exif_orientation(field.value.get_uint(0)?)
+    };
+    orientation().unwrap_or(Transform::Identity)
+}
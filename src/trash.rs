@@ -0,0 +1,168 @@
+//! Moving files to the trash, following the freedesktop.org Trash specification:
+//! https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::action::is_cross_device;
+use crate::layout::numbered_name;
+
+/// Moves the file at `path` into the trash, returning where it ended up.
+///
+/// Files go into the home trash (`$XDG_DATA_HOME/Trash`) if they're on the same filesystem as it,
+/// otherwise into `$topdir/.Trash-$uid` at the top of the filesystem the file lives on, so trashing
+/// never has to copy anything.
+pub fn move_to_trash(path: &Path) -> Result<PathBuf, io::Error> {
+    let path = fs::canonicalize(path)?;
+
+    let home_trash = home_trash()?;
+    match trash_into(&path, &home_trash) {
+        Err(e) if is_cross_device(&e) => trash_into(&path, &topdir_trash(&path)?),
+        result => result,
+    }
+}
+
+/// `$XDG_DATA_HOME/Trash`, falling back to `~/.local/share/Trash`.
+fn home_trash() -> Result<PathBuf, io::Error> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "neither $XDG_DATA_HOME nor $HOME are set",
+                ))
+            }
+        },
+    };
+    Ok(data_home.join("Trash"))
+}
+
+/// The per-user trash directory at the top of the filesystem `path` is on.
+#[cfg(unix)]
+fn topdir_trash(path: &Path) -> Result<PathBuf, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = fs::metadata(path)?.dev();
+    let mut topdir = path;
+    while let Some(parent) = topdir.parent() {
+        if fs::metadata(parent)?.dev() != device {
+            break;
+        }
+        topdir = parent;
+    }
+
+    // Safe, getuid can't fail and doesn't touch memory
+    let uid = unsafe { libc::getuid() };
+    Ok(topdir.join(format!(".Trash-{}", uid)))
+}
+
+#[cfg(not(unix))]
+fn topdir_trash(_path: &Path) -> Result<PathBuf, io::Error> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "trashing files on other drives isn't supported on this platform",
+    ))
+}
+
+/// Moves `path` into the trash directory `trash`, writing its `.trashinfo` file first.
+fn trash_into(path: &Path, trash: &Path) -> Result<PathBuf, io::Error> {
+    let files = trash.join("files");
+    let info = trash.join("info");
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy()
+        .into_owned();
+
+    // Creating the info file atomically is what claims a name in the trash, so keep trying
+    // numbered names until one of them is free
+    for attempt in 0.. {
+        let trashed_name = if attempt == 0 {
+            name.clone()
+        } else {
+            numbered_name(&name, attempt)
+        };
+        let info_path = info.join(format!("{}.trashinfo", trashed_name));
+        let mut info_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+
+        let trashed_path = files.join(&trashed_name);
+        if trashed_path.exists() {
+            // A leftover from something else, don't touch it and don't keep the claim either
+            fs::remove_file(&info_path)?;
+            continue;
+        }
+
+        let written = write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(path),
+            Local::now().format("%Y-%m-%dT%H:%M:%S")
+        )
+        .and_then(|_| info_file.sync_all())
+        .and_then(|_| fs::rename(path, &trashed_path));
+        return match written {
+            Ok(_) => Ok(trashed_path),
+            Err(e) => {
+                let _ = fs::remove_file(&info_path);
+                Err(e)
+            }
+        };
+    }
+
+    unreachable!("ran out of numbered names in the trash")
+}
+
+/// Moves `trashed`, a path returned by `move_to_trash`, back to `original` and removes its
+/// `.trashinfo` file.
+pub fn restore(trashed: &Path, original: &Path) -> Result<(), io::Error> {
+    fs::rename(trashed, original)?;
+
+    if let (Some(trash), Some(name)) =
+        (trashed.parent().and_then(Path::parent), trashed.file_name())
+    {
+        let mut info_name = name.to_os_string();
+        info_name.push(".trashinfo");
+        // The file is already back, a leftover info file only shows up as a broken entry
+        let _ = fs::remove_file(trash.join("info").join(info_name));
+    }
+    Ok(())
+}
+
+/// Percent-encodes a path the way URLs are, leaving `/` alone. Names that aren't valid UTF-8 are
+/// encoded byte for byte, so the path still leads back to the file.
+fn percent_encode(path: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str());
+    #[cfg(not(unix))]
+    let lossy = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+
+    let mut encoded = String::new();
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
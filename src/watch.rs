@@ -0,0 +1,245 @@
+//! Deduplicating a directory continuously, one image at a time as they land in it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::bktree::BkTree;
+use crate::{
+    action, closest_match, hash_image, hash_images, DedupOptions, DuplicateGroup, Duplicates,
+    Error, HashCache, ImageProperties, Progress, Scanner,
+};
+
+#[derive(Debug)]
+/// Every image of a watched directory that isn't a duplicate, for checking new images against.
+///
+/// Whatever watches the directory tells it about every file that shows up, changes or goes away.
+/// Each image that shows up is matched with the closest image already there, and the keeper of
+/// the two stays in the set while the other one is returned as a duplicate.
+pub struct Watch {
+    root: PathBuf,
+    options: DedupOptions,
+    images: HashMap<PathBuf, ImageProperties>,
+    /// The paths of `images` by hash. Nodes can't be taken out of it, so paths stay behind when
+    /// their image goes away or changes, and are only dropped when it's rebuilt.
+    tree: BkTree<PathBuf>,
+    /// How many paths in `tree` are left over like that.
+    stale: usize,
+}
+
+impl Watch {
+    /// Starts watching the directory at `root`, hashing every image already in it. Duplicates
+    /// among those are left alone, a regular `find_duplicates` is the way to deal with them.
+    ///
+    /// Hashes are looked up in and added to `cache` if there is one, `progress` is told about
+    /// every image along the way.
+    pub fn new(
+        root: &Path,
+        options: DedupOptions,
+        cache: Option<&Mutex<HashCache>>,
+        progress: &dyn Progress,
+    ) -> Result<(Watch, Vec<Error>), Error> {
+        // Events come with the watched path in front, it's easier if there's only one way to write it
+        let root = fs::canonicalize(root).map_err(|source| Error::Io {
+            path: root.to_path_buf(),
+            source,
+        })?;
+        let mut watch = Watch {
+            root,
+            options,
+            images: HashMap::new(),
+            tree: BkTree::new(),
+            stale: 0,
+        };
+        let skipped = watch.rescan(cache, progress);
+        Ok((watch, skipped))
+    }
+
+    /// The watched directory, with symlinks resolved.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// How many images are in the set.
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Forgets every image and hashes the directory all over again, for when events were missed.
+    pub fn rescan(
+        &mut self,
+        cache: Option<&Mutex<HashCache>>,
+        progress: &dyn Progress,
+    ) -> Vec<Error> {
+        let (found, mut skipped) = self.options.scanner.find_images(&self.root);
+        progress.found(found.len());
+        let files = found
+            .into_iter()
+            .map(|path| (path, self.root.as_path()))
+            .collect();
+
+        let mut images = HashMap::new();
+        for hashed in hash_images(files, &self.options.hash, cache, progress) {
+            match hashed {
+                Ok(img) => {
+                    images.insert(img.path.clone(), img);
+                }
+                Err(e) => skipped.push(e),
+            }
+        }
+        self.images = images;
+        self.rebuild_tree();
+        skipped
+    }
+
+    fn rebuild_tree(&mut self) {
+        self.tree = BkTree::new();
+        for (path, img) in &self.images {
+            self.tree.insert(img.hash.clone(), path.clone());
+        }
+        self.stale = 0;
+    }
+
+    fn insert(&mut self, image: ImageProperties) {
+        self.tree.insert(image.hash.clone(), image.path.clone());
+        if self.images.insert(image.path.clone(), image).is_some() {
+            self.forgot();
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if self.images.remove(path).is_some() {
+            self.forgot();
+        }
+    }
+
+    /// Counts another path left over in `tree`, rebuilding it once they outnumber the images
+    /// that are still there.
+    fn forgot(&mut self) {
+        self.stale += 1;
+        if self.stale > self.images.len() {
+            self.rebuild_tree();
+        }
+    }
+
+    /// Checks the file or directory at `path`, which was just created or modified, returning
+    /// every image in it that turned out to be a duplicate. Files a scan wouldn't look at are
+    /// ignored.
+    pub fn arrived(&mut self, path: &Path) -> Duplicates {
+        let mut found = Duplicates::default();
+        if !path.is_dir() {
+            match self.arrived_file(path) {
+                Ok(Some(group)) => found.groups.push(group),
+                Ok(None) => {}
+                Err(e) => found.skipped.push(e),
+            }
+            return found;
+        }
+
+        // A directory moved in whole only comes with one event. Every file is checked against
+        // the patterns relative to the watched directory, so none are applied here
+        let walk = Scanner {
+            recursive: true,
+            formats: self.options.scanner.formats.clone(),
+            ..Scanner::default()
+        };
+        let (files, skipped) = walk.find_images(path);
+        found.skipped.extend(skipped);
+        for file in files {
+            match self.arrived_file(&file) {
+                Ok(Some(group)) => found.groups.push(group),
+                Ok(None) => {}
+                Err(e) => found.skipped.push(e),
+            }
+        }
+        found
+    }
+
+    fn arrived_file(&mut self, path: &Path) -> Result<Option<DuplicateGroup>, Error> {
+        // Whatever was known about it is out of date now
+        self.remove(path);
+
+        match self.options.scanner.accepts(&self.root, path) {
+            Ok(true) => {}
+            Ok(false) => return Ok(None),
+            // Gone again before it could be checked
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(Error::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        }
+        let image = hash_image(
+            path.to_path_buf(),
+            &self.root,
+            &self.options.hash.hasher(),
+            None,
+            &(),
+        )?;
+
+        let mut found: Vec<&PathBuf> = image
+            .hashes()
+            .flat_map(|(_, hash)| self.tree.find(hash, self.options.threshold))
+            .collect();
+        found.sort();
+        found.dedup();
+        // Paths left over in the tree can be gone, or have a different image by now
+        let candidates: Vec<&ImageProperties> = found
+            .into_iter()
+            .filter_map(|path| self.images.get(path))
+            .filter(|img| img.closest_transform(&image).1 <= self.options.threshold)
+            .collect();
+        // Another name for an image that's already known, like a hardlink the action just made
+        if candidates
+            .iter()
+            .any(|img| action::is_same_file(&img.path, &image.path).unwrap_or(false))
+        {
+            return Ok(None);
+        }
+
+        let (closest, kind) = match closest_match(&image, candidates) {
+            Some((closest, kind)) => (closest.clone(), kind),
+            None => {
+                self.insert(image);
+                return Ok(None);
+            }
+        };
+
+        let mut images = vec![closest, image];
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+        let group = DuplicateGroup {
+            keeper: self.options.keep.choose(&images, |_| 0),
+            images,
+            kind,
+        };
+        for duplicate in group.duplicates() {
+            self.remove(&duplicate.path);
+        }
+        let keeper = group.keeper().clone();
+        if !self.images.contains_key(&keeper.path) {
+            self.insert(keeper);
+        }
+        Ok(Some(group))
+    }
+
+    /// Forgets the file at `path`, or everything under it if it was a directory.
+    pub fn removed(&mut self, path: &Path) {
+        let gone: Vec<PathBuf> = self
+            .images
+            .keys()
+            .filter(|image| image.starts_with(path))
+            .cloned()
+            .collect();
+        for image in gone {
+            self.remove(&image);
+        }
+    }
+}
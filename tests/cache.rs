@@ -0,0 +1,113 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use image_dedup_cli::cache::FileStamp;
+use image_dedup_cli::{find_duplicates, DedupOptions, HashCache, Progress};
+
+use common::{pattern, write_fixtures};
+
+#[derive(Default)]
+/// The file names of every image `find_duplicates` got through, and whether it came from the cache.
+struct Hashed(Mutex<Vec<(String, bool)>>);
+
+impl Progress for Hashed {
+    fn hashed(&self, path: &Path, cached: bool) {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        self.0.lock().unwrap().push((name, cached));
+    }
+}
+
+impl Hashed {
+    fn cached(&self, name: &str) -> bool {
+        let hashed = self.0.lock().unwrap();
+        let (_, cached) = hashed.iter().find(|(hashed, _)| hashed == name).unwrap();
+        *cached
+    }
+}
+
+/// Checks `dir` with `cache`, saving the cache afterwards.
+fn run(dir: &Path, cache: HashCache) -> Hashed {
+    let cache = Mutex::new(cache);
+    let hashed = Hashed::default();
+    let found = find_duplicates(&[dir], &DedupOptions::default(), Some(&cache), &hashed);
+    assert!(found.skipped.is_empty());
+    cache.into_inner().unwrap().save().unwrap();
+    hashed
+}
+
+fn stamp(path: &Path) -> FileStamp {
+    FileStamp::new(&fs::metadata(path).unwrap()).unwrap()
+}
+
+fn canonical(path: PathBuf) -> PathBuf {
+    fs::canonicalize(path).unwrap()
+}
+
+#[test]
+fn reuses_hashes_of_unchanged_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    write_fixtures(&images);
+    let cache_path = dir.path().join("cache/hashes.bin");
+
+    let first = run(&images, HashCache::open(cache_path.clone()).unwrap());
+    assert!(first.0.lock().unwrap().iter().all(|(_, cached)| !cached));
+    assert!(cache_path.exists());
+
+    // Rewritten with something else, so it has to be hashed again
+    pattern(300, 200, 9).save(images.join("b.png")).unwrap();
+    let second = run(&images, HashCache::open(cache_path.clone()).unwrap());
+    assert!(second.cached("a_reencoded.jpg"));
+    assert!(second.cached("a_small.png"));
+    assert!(!second.cached("b.png"));
+
+    let b = canonical(images.join("b.png"));
+    let mut cache = HashCache::open(cache_path.clone()).unwrap();
+    let cached = cache.get(&b, stamp(&b)).unwrap();
+    assert_eq!((cached.width, cached.height), (300, 200));
+}
+
+#[test]
+fn prunes_files_that_are_gone() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    write_fixtures(&images);
+    let cache_path = dir.path().join("hashes.bin");
+    run(&images, HashCache::open(cache_path.clone()).unwrap());
+
+    let b = canonical(images.join("b.png"));
+    let small = canonical(images.join("a_small.png"));
+    let (b_stamp, small_stamp) = (stamp(&b), stamp(&small));
+    fs::remove_file(&b).unwrap();
+    // Saving without using any entry still keeps the ones whose file is unchanged
+    HashCache::open(cache_path.clone()).unwrap().save().unwrap();
+
+    let mut cache = HashCache::open(cache_path).unwrap();
+    assert!(cache.get(&b, b_stamp).is_none());
+    assert!(cache.get(&small, small_stamp).is_some());
+}
+
+#[test]
+fn starts_over_from_an_unreadable_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    write_fixtures(&images);
+    let cache_path = dir.path().join("hashes.bin");
+    fs::write(&cache_path, "not a cache").unwrap();
+
+    assert!(HashCache::open(cache_path.clone()).is_err());
+    let hashed = run(&images, HashCache::new(cache_path.clone()));
+    assert!(!hashed.cached("b.png"));
+
+    // Saving replaced it with a cache that works
+    let hashed = run(&images, HashCache::open(cache_path.clone()).unwrap());
+    assert!(hashed.cached("b.png"));
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names.len(), 2, "temporary files left behind: {:?}", names);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::write_fixtures;
+
+#[test]
+fn dry_run_prints_planned_moves() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_image-dedup-cli"))
+        .arg(dir.path())
+        .args(["--dry-run", "--no-cache"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Would move"), "{}", stdout);
+    assert!(dir.path().join("a.png").exists());
+    assert!(dir.path().join("a_copy.png").exists());
+    assert!(!dir.path().join("duplicates").exists());
+}
+
+#[test]
+fn exit_code_tells_outcomes_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(&dir.path().join("dupes"));
+    fs::create_dir(dir.path().join("empty")).unwrap();
+    let status = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_image-dedup-cli"))
+            .current_dir(dir.path())
+            .args(args)
+            .args(["--dry-run", "--no-cache"])
+            .status()
+            .unwrap()
+            .code()
+    };
+
+    assert_eq!(status(&["empty"]), Some(0));
+    assert_eq!(status(&["dupes"]), Some(1));
+    assert_eq!(status(&["dupes", "missing"]), Some(2));
+    assert_eq!(status(&["dupes", "--exclude", "[a"]), Some(3));
+    assert_eq!(status(&["--no-such-flag"]), Some(3));
+}
+
+#[test]
+fn directories_before_a_subcommand_are_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(&dir.path().join("photos"));
+    fs::create_dir(dir.path().join("incoming")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_image-dedup-cli"))
+        .current_dir(dir.path())
+        .args([
+            "photos",
+            "--no-cache",
+            "check",
+            "incoming",
+            "--against",
+            "photos",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("\"photos\""), "{}", stderr);
+}
@@ -0,0 +1,34 @@
+//! Fixture images generated on the fly, so the tests don't need any binary files checked in.
+
+use std::fs;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, Rgb};
+
+/// A smooth pattern that looks different for every `seed`, but survives re-encoding and resizing.
+pub fn pattern(width: u32, height: u32, seed: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+        let v = ((x * 255 / width) ^ (y * 255 / height).wrapping_mul(seed)) as u8;
+        Rgb([v, (x * 7 + seed) as u8, (y * 3) as u8])
+    }))
+}
+
+/// Fills `dir` with:
+///
+/// - `a.png` and `a_copy.png`, byte for byte the same
+/// - `a_reencoded.jpg` and `a_small.png`, the same picture re-encoded and shrunk
+/// - `b.png`, something else entirely
+/// - `notes.txt`, not an image at all
+pub fn write_fixtures(dir: &Path) {
+    fs::create_dir_all(dir).unwrap();
+    let a = pattern(256, 192, 1);
+    a.save(dir.join("a.png")).unwrap();
+    fs::copy(dir.join("a.png"), dir.join("a_copy.png")).unwrap();
+    a.save(dir.join("a_reencoded.jpg")).unwrap();
+    a.resize_exact(128, 96, FilterType::Triangle)
+        .save(dir.join("a_small.png"))
+        .unwrap();
+    pattern(256, 192, 5).save(dir.join("b.png")).unwrap();
+    fs::write(dir.join("notes.txt"), "not an image").unwrap();
+}
@@ -0,0 +1,475 @@
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use glob::Pattern;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use image_dedup_cli::{
+    find_duplicates, find_matches, DedupOptions, Error, KeepPolicy, MatchKind, Scanner, Transform,
+};
+
+use common::{pattern, write_fixtures};
+
+/// The file names of `paths`, sorted.
+fn names<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<String> {
+    let mut names: Vec<String> = paths
+        .into_iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn groups_identical_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+
+    let groups = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &()).groups;
+    let exact: Vec<_> = groups
+        .iter()
+        .filter(|group| group.kind == MatchKind::Exact)
+        .collect();
+    assert_eq!(exact.len(), 1);
+    assert_eq!(
+        names(exact[0].images.iter().map(|img| img.path.as_path())),
+        ["a.png", "a_copy.png"]
+    );
+}
+
+#[test]
+fn groups_similar_images_within_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+
+    let options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    let grouped: Vec<&Path> = groups
+        .iter()
+        .flat_map(|group| group.images.iter().map(|img| img.path.as_path()))
+        .collect();
+    let mut grouped = names(grouped);
+    grouped.dedup();
+    assert_eq!(
+        grouped,
+        ["a.png", "a_copy.png", "a_reencoded.jpg", "a_small.png"]
+    );
+
+    // Anything goes, without the search range overflowing
+    let options = DedupOptions {
+        threshold: u32::MAX,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(groups
+        .iter()
+        .any(|group| group.images.iter().any(|img| img.path.ends_with("b.png"))));
+}
+
+#[test]
+fn keeps_according_to_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let big = pattern(256, 192, 1);
+    big.save(dir.path().join("big.png")).unwrap();
+    big.resize_exact(64, 48, FilterType::Triangle)
+        .save(dir.path().join("small.png"))
+        .unwrap();
+
+    for (keep, kept) in [
+        (KeepPolicy::HighestResolution, "big.png"),
+        (KeepPolicy::Smallest, "small.png"),
+    ] {
+        let options = DedupOptions {
+            threshold: 10,
+            keep,
+            ..DedupOptions::default()
+        };
+        let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].keeper().path, dir.path().join(kept));
+    }
+}
+
+#[test]
+fn scanner_respects_recursion_and_globs() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+    write_fixtures(&dir.path().join("nested"));
+    let found = |scanner: &Scanner| {
+        names(
+            scanner
+                .find_images(dir.path())
+                .0
+                .iter()
+                .map(|p| p.as_path()),
+        )
+    };
+
+    assert_eq!(
+        found(&Scanner::default()),
+        [
+            "a.png",
+            "a_copy.png",
+            "a_reencoded.jpg",
+            "a_small.png",
+            "b.png"
+        ]
+    );
+    let recursive = Scanner {
+        recursive: true,
+        ..Scanner::default()
+    };
+    assert_eq!(found(&recursive).len(), 10);
+    let include = Scanner {
+        recursive: true,
+        include: vec![Pattern::new("*.jpg").unwrap()],
+        ..Scanner::default()
+    };
+    assert_eq!(found(&include), ["a_reencoded.jpg", "a_reencoded.jpg"]);
+    let exclude = Scanner {
+        recursive: true,
+        exclude: vec![Pattern::new("nested").unwrap(), Pattern::new("a*").unwrap()],
+        ..Scanner::default()
+    };
+    assert_eq!(found(&exclude), ["b.png"]);
+}
+
+#[test]
+fn overlapping_roots_dont_duplicate_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let sub = dir.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+    pattern(256, 192, 1).save(sub.join("only.png")).unwrap();
+
+    let options = DedupOptions {
+        scanner: Scanner {
+            recursive: true,
+            ..Scanner::default()
+        },
+        ..DedupOptions::default()
+    };
+    let found = find_duplicates(&[dir.path(), &sub, &sub], &options, None, &());
+    assert!(found.groups.is_empty());
+    assert!(found.skipped.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_links_arent_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    fs::create_dir(&a).unwrap();
+    fs::create_dir(&b).unwrap();
+    pattern(256, 192, 1).save(a.join("x.png")).unwrap();
+    fs::hard_link(a.join("x.png"), b.join("x.png")).unwrap();
+    fs::hard_link(a.join("x.png"), a.join("y.png")).unwrap();
+
+    // Like the links a hardlink run leaves behind, both within a directory and across them
+    for roots in [vec![a.as_path()], vec![a.as_path(), b.as_path()]] {
+        let found = find_duplicates(&roots, &DedupOptions::default(), None, &());
+        assert!(found.groups.is_empty(), "{:?}", found.groups);
+        assert!(found.skipped.is_empty());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_loops_are_skipped_quietly() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(&dir.path().join("photos"));
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("photos/back_up")).unwrap();
+
+    let options = DedupOptions {
+        scanner: Scanner {
+            recursive: true,
+            ..Scanner::default()
+        },
+        ..DedupOptions::default()
+    };
+    let found = find_duplicates(&[dir.path()], &options, None, &());
+    assert!(found.skipped.is_empty(), "{:?}", found.skipped);
+    let exact = found
+        .groups
+        .iter()
+        .find(|group| group.kind == MatchKind::Exact)
+        .unwrap();
+    assert_eq!(
+        names(exact.images.iter().map(|img| img.path.as_path())),
+        ["a.png", "a_copy.png"]
+    );
+}
+
+#[test]
+fn scanner_skips_duplicates_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(&dir.path().join("duplicates"));
+    fs::copy(
+        dir.path().join("duplicates/b.png"),
+        dir.path().join("b.png"),
+    )
+    .unwrap();
+
+    let scanner = Scanner {
+        recursive: true,
+        ..Scanner::default()
+    };
+    assert_eq!(
+        names(
+            scanner
+                .find_images(dir.path())
+                .0
+                .iter()
+                .map(|p| p.as_path())
+        ),
+        ["b.png"]
+    );
+}
+
+#[test]
+fn reports_images_that_cant_be_decoded() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+    // Looks like a PNG from its signature, but there's nothing after it
+    fs::write(dir.path().join("broken.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+    let found = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &());
+    assert!(!found.groups.is_empty());
+    assert_eq!(found.skipped.len(), 1);
+    match &found.skipped[0] {
+        Error::Decode { path, .. } => assert_eq!(path, &dir.path().join("broken.png")),
+        err => panic!("expected a decode error, got {:?}", err),
+    }
+}
+
+#[test]
+fn text_files_with_image_like_signatures_arent_images() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("todo.txt"), "P1 priorities for the week\n").unwrap();
+    fs::write(dir.path().join("car.txt"), "BMW service notes\n").unwrap();
+    fs::write(
+        dir.path().join("pam.txt"),
+        "P7 meeting\nWIDTH of the hall\n",
+    )
+    .unwrap();
+    let a = pattern(64, 48, 1);
+    a.save(dir.path().join("a.ppm")).unwrap();
+    a.save(dir.path().join("a.bmp")).unwrap();
+    fs::write(
+        dir.path().join("commented.pgm"),
+        b"P5\n# made by hand\n2 1\n255\n\x00\xff",
+    )
+    .unwrap();
+
+    let (found, skipped) = Scanner::default().find_images(dir.path());
+    assert!(skipped.is_empty());
+    assert_eq!(
+        names(found.iter().map(|p| p.as_path())),
+        ["a.bmp", "a.ppm", "commented.pgm"]
+    );
+    let found = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &());
+    assert!(found.skipped.is_empty());
+}
+
+#[test]
+fn reports_missing_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+
+    let found = find_duplicates(&[missing.as_path()], &DedupOptions::default(), None, &());
+    assert!(found.groups.is_empty());
+    assert!(matches!(&found.skipped[..], [Error::Io { path, .. }] if path == &missing));
+}
+
+#[test]
+fn matches_queries_against_reference() {
+    let dir = tempfile::tempdir().unwrap();
+    let (reference, incoming) = (dir.path().join("reference"), dir.path().join("incoming"));
+    write_fixtures(&reference);
+    fs::create_dir(&incoming).unwrap();
+    fs::copy(reference.join("a_small.png"), incoming.join("known.png")).unwrap();
+    pattern(256, 192, 9).save(incoming.join("new.png")).unwrap();
+    // Duplicates of each other, but not of anything in the reference
+    fs::copy(incoming.join("new.png"), incoming.join("new_copy.png")).unwrap();
+
+    let found = find_matches(
+        &[incoming.as_path()],
+        &reference,
+        &DedupOptions::default(),
+        None,
+        &(),
+    );
+    assert!(found.skipped.is_empty());
+    assert_eq!(found.groups.len(), 1);
+    let group = &found.groups[0];
+    assert_eq!(group.kind, MatchKind::Exact);
+    assert_eq!(group.keeper().path, reference.join("a_small.png"));
+    assert_eq!(group.images[1].path, incoming.join("known.png"));
+}
+
+#[test]
+fn matches_rotated_and_mirrored_images_when_rotation_invariant() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = pattern(256, 192, 1);
+    a.save(dir.path().join("a.png")).unwrap();
+    a.rotate90().save(dir.path().join("a_rot.png")).unwrap();
+    a.fliph().save(dir.path().join("a_flip.png")).unwrap();
+    pattern(256, 192, 5).save(dir.path().join("b.png")).unwrap();
+
+    let mut options = DedupOptions {
+        keep: KeepPolicy::ShortestPath,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(groups.is_empty());
+
+    options.hash.rotation_invariant = true;
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert_eq!(groups.len(), 1);
+    let group = &groups[0];
+    assert_eq!(group.keeper().path, dir.path().join("a.png"));
+    let relations: Vec<_> = group
+        .duplicates()
+        .map(|img| (names([img.path.as_path()]), group.relation(img)))
+        .collect();
+    assert_eq!(
+        relations,
+        [
+            (
+                vec!["a_flip.png".to_string()],
+                (Transform::FlipHorizontal, 0)
+            ),
+            (vec!["a_rot.png".to_string()], (Transform::Rotate90, 0)),
+        ]
+    );
+}
+
+/// Saves `image` to `path` as a JPEG with its pixels stored sideways, along with the EXIF
+/// orientation that turns them upright again, like a phone camera would.
+fn save_sideways_jpeg(image: &DynamicImage, path: &Path) {
+    let mut jpeg = Vec::new();
+    image
+        .rotate270()
+        .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+        .unwrap();
+
+    let orientation = exif::Field {
+        tag: exif::Tag::Orientation,
+        ifd_num: exif::In::PRIMARY,
+        value: exif::Value::Short(vec![6]),
+    };
+    let mut writer = exif::experimental::Writer::new();
+    writer.push_field(&orientation);
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).unwrap();
+    let tiff = tiff.into_inner();
+
+    // The APP1 segment holding the EXIF data goes right after the start of image marker
+    let mut app1 = vec![0xff, 0xe1];
+    app1.extend_from_slice(&(2 + 6 + tiff.len() as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(&tiff);
+    jpeg.splice(2..2, app1);
+    fs::write(path, jpeg).unwrap();
+}
+
+#[test]
+fn turns_images_upright_by_exif_orientation() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = pattern(256, 192, 1);
+    a.save(dir.path().join("a.png")).unwrap();
+    save_sideways_jpeg(&a, &dir.path().join("sideways.jpg"));
+
+    let mut options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert_eq!(groups.len(), 1);
+    let sideways = &groups[0].images[1];
+    assert_eq!(sideways.path, dir.path().join("sideways.jpg"));
+    assert_eq!((sideways.width, sideways.height), (256, 192));
+
+    options.hash.exif_orientation = false;
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(groups.is_empty());
+}
+
+#[test]
+fn matches_framed_images_when_trimming_borders() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = pattern(256, 192, 1);
+    a.save(dir.path().join("a.png")).unwrap();
+    let mut framed = RgbImage::from_pixel(320, 240, Rgb([128, 128, 128]));
+    imageops::overlay(&mut framed, &a.to_rgb8(), 32, 24);
+    framed.save(dir.path().join("a_framed.png")).unwrap();
+
+    let mut options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(groups.is_empty());
+
+    options.hash.trim_borders = true;
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].kind, MatchKind::Perceptual);
+}
+
+#[test]
+fn finds_crops_when_detecting_crops() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = pattern(400, 300, 1);
+    a.save(dir.path().join("a.png")).unwrap();
+    // The middle 70% of each side, so about half of the picture
+    a.crop_imm(60, 45, 280, 210)
+        .save(dir.path().join("a_crop.png"))
+        .unwrap();
+    pattern(400, 300, 5).save(dir.path().join("b.png")).unwrap();
+
+    let mut options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(groups.is_empty());
+
+    options.hash.detect_crops = true;
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert_eq!(groups.len(), 1);
+    let group = &groups[0];
+    assert_eq!(group.kind, MatchKind::Crop);
+    assert_eq!(
+        names(group.images.iter().map(|img| img.path.as_path())),
+        ["a.png", "a_crop.png"]
+    );
+    let overlap = group.overlap().unwrap();
+    assert!((overlap - 0.49).abs() < 0.01, "overlap {}", overlap);
+}
+
+#[test]
+fn tiny_images_arent_crops() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = pattern(40, 30, 1);
+    a.save(dir.path().join("a.png")).unwrap();
+    a.crop_imm(6, 4, 28, 21)
+        .save(dir.path().join("a_crop.png"))
+        .unwrap();
+    pattern(1, 1, 1).save(dir.path().join("dot.png")).unwrap();
+    pattern(3, 2, 1).save(dir.path().join("speck.png")).unwrap();
+
+    let mut options = DedupOptions::default();
+    options.hash.detect_crops = true;
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    assert!(
+        groups.iter().all(|group| group.kind != MatchKind::Crop),
+        "{:?}",
+        groups
+    );
+}
@@ -0,0 +1,308 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use image_dedup_cli::{
+    find_duplicates, journal, Action, DedupOptions, Error, Executor, ImageProperties, Journal,
+    Layout, MatchKind,
+};
+
+use common::{pattern, write_fixtures};
+
+/// Undoes the journal at `path`, failing on any change that couldn't be undone and returning how
+/// many were.
+fn undo(path: &Path) -> usize {
+    let changes = journal::undo(path).unwrap();
+    for change in &changes {
+        if let Err(err) = &change.result {
+            panic!("couldn't undo {:?}: {}", change.entry.original, err);
+        }
+    }
+    changes.len()
+}
+
+#[test]
+fn moves_duplicates_and_undoes_it() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+    let journal_path = dir.path().join("journal.jsonl");
+
+    let groups = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &()).groups;
+    let mut executor = Executor::new(&groups, Action::Move, Layout::Mirror, None)
+        .with_journal(Journal::new(journal_path.clone()));
+    let mut moved = 0;
+    executor.execute(&groups, |_, _, description, result| {
+        result.unwrap_or_else(|err| panic!("failed to {}: {}", description, err));
+        moved += 1;
+    });
+    assert!(moved > 0);
+    assert!(!dir.path().join("a_copy.png").exists() || !dir.path().join("a.png").exists());
+    assert_eq!(
+        fs::read_dir(dir.path().join("duplicates")).unwrap().count(),
+        moved
+    );
+
+    assert_eq!(undo(&journal_path), moved);
+    assert!(dir.path().join("a.png").exists());
+    assert!(dir.path().join("a_copy.png").exists());
+    assert_eq!(
+        fs::read_dir(dir.path().join("duplicates")).unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn runs_never_share_a_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let journal_path = dir.path().join("journals/run.jsonl");
+    let mut journals = Vec::new();
+    for run in ["first", "second"] {
+        let images = dir.path().join(run);
+        write_fixtures(&images);
+        let groups = find_duplicates(&[&images], &DedupOptions::default(), None, &()).groups;
+        // Both runs started in the same second, so they were given the same name
+        let mut executor = Executor::new(&groups, Action::Move, Layout::Mirror, None)
+            .with_journal(Journal::new_file(journal_path.clone()));
+        executor.execute(&groups, |_, _, description, result| {
+            result.unwrap_or_else(|err| panic!("failed to {}: {}", description, err));
+        });
+        journals.push(executor.journal().unwrap().path().to_path_buf());
+    }
+    assert_eq!(journals[0], journal_path);
+    assert_eq!(journals[1], dir.path().join("journals/run.2.jsonl"));
+
+    assert!(undo(&journals[1]) > 0);
+    assert!(dir.path().join("second/a_copy.png").exists());
+    assert!(!dir.path().join("first/a_copy.png").exists());
+}
+
+#[test]
+fn never_acts_on_the_kept_file_itself() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+
+    // A group that somehow ended up with the same file twice
+    let mut groups = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &()).groups;
+    groups.retain(|group| group.kind == MatchKind::Exact);
+    let keeper = groups[0].keeper().clone();
+    groups[0].images = vec![keeper.clone(), keeper];
+    groups[0].keeper = 0;
+
+    let mut executor = Executor::new(&groups, Action::Delete, Layout::Mirror, None);
+    executor.execute(&groups, |_, duplicate, _, _| {
+        panic!("acted on {:?}", duplicate.path)
+    });
+    assert!(groups[0].keeper().path.exists());
+}
+
+#[test]
+fn only_links_exact_copies() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+    let options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let read = |name: &str| fs::read(dir.path().join(name)).unwrap();
+    let before: Vec<_> = ["a.png", "a_small.png"]
+        .iter()
+        .map(|name| read(name))
+        .collect();
+
+    // a.png is kept out of its identical copy, but is then a duplicate of the re-encoded JPEG
+    let groups = find_duplicates(&[dir.path()], &options, None, &()).groups;
+    let mut executor = Executor::new(&groups, Action::Hardlink, Layout::Mirror, None);
+    let (mut linked, mut failed) = (Vec::new(), Vec::new());
+    executor.execute(&groups, |_, duplicate, description, result| {
+        let name = duplicate
+            .path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        match result {
+            Ok(()) => linked.push((name, description.to_string())),
+            Err(Error::Action { .. }) => failed.push(name),
+            Err(err) => panic!("failed to {}: {}", description, err),
+        }
+    });
+    failed.sort();
+    assert_eq!(failed, ["a.png", "a_small.png"]);
+    assert_eq!(linked.len(), 1);
+    assert_eq!(linked[0].0, "a_copy.png");
+    assert!(linked[0].1.ends_with("a.png\""), "{}", linked[0].1);
+    assert_eq!(read("a_copy.png"), read("a.png"));
+    let after: Vec<_> = ["a.png", "a_small.png"]
+        .iter()
+        .map(|name| read(name))
+        .collect();
+    assert_eq!(after, before);
+}
+
+#[test]
+fn dry_run_leaves_files_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+
+    let groups = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &()).groups;
+    let mut executor = Executor::new(&groups, Action::Delete, Layout::Mirror, None).dry_run(true);
+    let mut descriptions = Vec::new();
+    executor.execute(&groups, |_, _, description, result| {
+        assert!(result.is_ok());
+        descriptions.push(description.to_string());
+    });
+    assert!(!descriptions.is_empty());
+    assert!(descriptions.iter().all(|d| d.starts_with("delete")));
+    assert!(dir.path().join("a.png").exists());
+    assert!(dir.path().join("a_copy.png").exists());
+}
+
+#[test]
+fn numbers_destinations_instead_of_overwriting() {
+    let dir = tempfile::tempdir().unwrap();
+    write_fixtures(dir.path());
+    fs::create_dir(dir.path().join("duplicates")).unwrap();
+    fs::write(dir.path().join("duplicates/a_copy.png"), "in the way").unwrap();
+    fs::write(dir.path().join("duplicates/a.png"), "in the way").unwrap();
+
+    let groups = find_duplicates(&[dir.path()], &DedupOptions::default(), None, &()).groups;
+    let mut executor = Executor::new(&groups, Action::Copy, Layout::Mirror, None);
+    let exact = groups
+        .iter()
+        .find(|group| group.kind == MatchKind::Exact)
+        .unwrap();
+    let duplicate = exact.duplicates().next().unwrap().clone();
+    let destination = executor
+        .plan()
+        .destination(&duplicate)
+        .unwrap()
+        .to_path_buf();
+    assert_eq!(
+        destination.file_name().unwrap().to_string_lossy(),
+        image_dedup_cli::layout::numbered_name(
+            &duplicate.path.file_name().unwrap().to_string_lossy(),
+            2
+        )
+    );
+
+    executor.execute(&groups, |_, _, _, result| result.unwrap());
+    assert_eq!(
+        fs::read(&destination).unwrap(),
+        fs::read(&duplicate.path).unwrap()
+    );
+    assert_eq!(
+        fs::read(dir.path().join("duplicates/a.png")).unwrap(),
+        b"in the way"
+    );
+}
+
+/// Runs `action` on a directory holding one image and an identical copy of it, with a journal,
+/// returning the kept image and its duplicate.
+fn act_on_identical_pair(
+    dir: &Path,
+    action: Action,
+    journal_path: &Path,
+) -> (ImageProperties, ImageProperties) {
+    pattern(64, 48, 1).save(dir.join("a.png")).unwrap();
+    fs::copy(dir.join("a.png"), dir.join("a_copy.png")).unwrap();
+
+    let groups = find_duplicates(&[dir], &DedupOptions::default(), None, &()).groups;
+    assert_eq!(groups.len(), 1);
+    let mut executor = Executor::new(&groups, action, Layout::Mirror, None)
+        .with_journal(Journal::new(journal_path.to_path_buf()));
+    executor.execute(&groups, |_, _, description, result| {
+        result.unwrap_or_else(|err| panic!("failed to {}: {}", description, err));
+    });
+    let group = &groups[0];
+    let duplicate = group.duplicates().next().unwrap().clone();
+    (group.keeper().clone(), duplicate)
+}
+
+#[cfg(unix)]
+#[test]
+fn hardlinks_duplicates_and_undoes_it() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    fs::create_dir(&images).unwrap();
+    let journal_path = dir.path().join("journal.jsonl");
+    let (keeper, duplicate) = act_on_identical_pair(&images, Action::Hardlink, &journal_path);
+    let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+    assert_eq!(inode(&duplicate.path), inode(&keeper.path));
+    assert_eq!(fs::metadata(&keeper.path).unwrap().nlink(), 2);
+
+    assert_eq!(undo(&journal_path), 1);
+    assert_ne!(inode(&duplicate.path), inode(&keeper.path));
+    assert_eq!(fs::metadata(&keeper.path).unwrap().nlink(), 1);
+    assert_eq!(
+        fs::read(&duplicate.path).unwrap(),
+        fs::read(&keeper.path).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_duplicates_and_undoes_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    fs::create_dir(&images).unwrap();
+    let journal_path = dir.path().join("journal.jsonl");
+    let (keeper, duplicate) = act_on_identical_pair(&images, Action::Symlink, &journal_path);
+    assert!(fs::symlink_metadata(&duplicate.path)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::read_link(&duplicate.path).unwrap(),
+        fs::canonicalize(&keeper.path).unwrap()
+    );
+
+    assert_eq!(undo(&journal_path), 1);
+    assert!(fs::symlink_metadata(&duplicate.path)
+        .unwrap()
+        .file_type()
+        .is_file());
+    assert_eq!(
+        fs::read(&duplicate.path).unwrap(),
+        fs::read(&keeper.path).unwrap()
+    );
+}
+
+#[test]
+fn trashes_duplicates_and_undoes_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let images = dir.path().join("images");
+    fs::create_dir(&images).unwrap();
+    // Nothing else in these tests looks at it, so there's no one to race with
+    let data_home = dir.path().join("data");
+    env::set_var("XDG_DATA_HOME", &data_home);
+    let journal_path = dir.path().join("journal.jsonl");
+    let (keeper, duplicate) = act_on_identical_pair(&images, Action::Trash, &journal_path);
+    let original = fs::canonicalize(&images)
+        .unwrap()
+        .join(duplicate.path.file_name().unwrap());
+    assert!(!duplicate.path.exists());
+
+    let trash = data_home.join("Trash");
+    let name = duplicate.path.file_name().unwrap().to_string_lossy();
+    let trashed = trash.join("files").join(&*name);
+    assert_eq!(fs::read(&trashed).unwrap(), fs::read(&keeper.path).unwrap());
+    let info_path = trash.join("info").join(format!("{}.trashinfo", name));
+    let info = fs::read_to_string(&info_path).unwrap();
+    let lines: Vec<&str> = info.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", info);
+    assert_eq!(lines[0], "[Trash Info]");
+    assert_eq!(lines[1], format!("Path={}", original.display()));
+    assert!(lines[2].starts_with("DeletionDate="), "{}", info);
+
+    assert_eq!(undo(&journal_path), 1);
+    assert_eq!(
+        fs::read(&duplicate.path).unwrap(),
+        fs::read(&keeper.path).unwrap()
+    );
+    assert!(!trashed.exists());
+    assert!(!info_path.exists());
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::fs;
+
+use glob::Pattern;
+use image_dedup_cli::{
+    find_matches_in_index, DedupOptions, HashOptions, Index, MatchKind, Scanner,
+};
+
+use common::{pattern, write_fixtures};
+
+#[test]
+fn updates_only_what_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let collection = dir.path().join("collection");
+    write_fixtures(&collection);
+    let index_path = dir.path().join("collection.idx");
+
+    let scanner = Scanner {
+        exclude: vec![Pattern::new("a_small.png").unwrap()],
+        ..Scanner::default()
+    };
+    let mut index = Index::new(
+        index_path.clone(),
+        &collection,
+        scanner,
+        HashOptions::default(),
+    )
+    .unwrap();
+    let built = index.update(None, &()).unwrap();
+    assert_eq!(
+        (built.added, built.changed, built.removed, built.unchanged),
+        (4, 0, 0, 0)
+    );
+    index.save().unwrap();
+
+    fs::remove_file(collection.join("a_copy.png")).unwrap();
+    pattern(64, 48, 9).save(collection.join("b.png")).unwrap();
+    pattern(256, 192, 3).save(collection.join("c.png")).unwrap();
+
+    // The exclude pattern is kept in the index, so a_small.png stays out of it
+    let mut index = Index::open(index_path.clone()).unwrap();
+    let updated = index.update(None, &()).unwrap();
+    assert_eq!(
+        (
+            updated.added,
+            updated.changed,
+            updated.removed,
+            updated.unchanged
+        ),
+        (1, 1, 1, 2)
+    );
+    assert!(updated.skipped.is_empty());
+    assert_eq!(index.len(), 4);
+    index.save().unwrap();
+
+    let index = Index::open(index_path).unwrap();
+    let query = dir.path().join("query.png");
+    pattern(256, 192, 1).save(&query).unwrap();
+    let matches = index.query(&query, 10).unwrap();
+    let mut names: Vec<_> = matches
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a.png", "a_reencoded.jpg"]);
+    assert_eq!(matches[0].1, 0);
+
+    // An image in the collection doesn't match itself
+    let matches = index.query(&collection.join("c.png"), 0).unwrap();
+    assert!(matches.is_empty());
+}
+
+#[test]
+fn checks_queries_against_an_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let collection = dir.path().join("collection");
+    fs::create_dir(&collection).unwrap();
+    pattern(256, 192, 1).save(collection.join("a.png")).unwrap();
+    pattern(256, 192, 5).save(collection.join("b.png")).unwrap();
+    let mut index = Index::new(
+        dir.path().join("collection.idx"),
+        &collection,
+        Scanner::default(),
+        HashOptions::default(),
+    )
+    .unwrap();
+    index.update(None, &()).unwrap();
+
+    let incoming = dir.path().join("incoming");
+    fs::create_dir(&incoming).unwrap();
+    fs::copy(collection.join("a.png"), incoming.join("a.png")).unwrap();
+    pattern(256, 192, 1).save(incoming.join("a.jpg")).unwrap();
+    pattern(256, 192, 3).save(incoming.join("c.png")).unwrap();
+    // Gone since the index was updated, so there's nothing to keep in its place
+    fs::remove_file(collection.join("b.png")).unwrap();
+    pattern(256, 192, 5).save(incoming.join("b.png")).unwrap();
+
+    let options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let found = find_matches_in_index(&[&incoming], &index, &options, None, &());
+    assert!(found.skipped.is_empty());
+    let matches: Vec<_> = found
+        .groups
+        .iter()
+        .map(|group| {
+            (
+                group.images[1].path.file_name().unwrap().to_owned(),
+                group.keeper().path.clone(),
+                group.kind,
+            )
+        })
+        .collect();
+    let kept = index.root().join("a.png");
+    assert_eq!(
+        matches,
+        [
+            ("a.jpg".into(), kept.clone(), MatchKind::Perceptual),
+            ("a.png".into(), kept, MatchKind::Exact),
+        ]
+    );
+}
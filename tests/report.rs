@@ -0,0 +1,82 @@
+mod common;
+
+use std::path::Path;
+
+use image_dedup_cli::report::{write_report, ReportFormat};
+use image_dedup_cli::{find_duplicates, Action, DedupOptions, Layout, MatchKind, Plan};
+
+use common::write_fixtures;
+
+// Windows doesn't allow quotes in file names
+#[cfg(unix)]
+#[test]
+fn reports_groups_as_json_and_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = dir.path().join("my \"best\", photos");
+    write_fixtures(&photos);
+
+    let mut groups = find_duplicates(&[&photos], &DedupOptions::default(), None, &()).groups;
+    groups.retain(|group| group.kind == MatchKind::Exact);
+    assert_eq!(groups.len(), 1);
+    let plan = Plan::new(&groups, Action::Move, Layout::Mirror, None);
+    let keeper = &groups[0].keeper().path;
+    let duplicate = groups[0].duplicates().next().unwrap();
+    let destination = plan.destination(duplicate).unwrap();
+    let duplicate = &duplicate.path;
+    assert_eq!(
+        destination,
+        photos
+            .join("duplicates")
+            .join(duplicate.file_name().unwrap())
+    );
+
+    let mut json = Vec::new();
+    write_report(&mut json, &groups, Action::Move, &plan, ReportFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let group = &json[0];
+    assert_eq!(group["match"], "exact");
+    assert_eq!(group["keeper"], keeper.to_str().unwrap());
+    let images = group["images"].as_array().unwrap();
+    assert_eq!(images.len(), 2);
+    for image in images {
+        if image["path"] == keeper.to_str().unwrap() {
+            assert_eq!(image["keep"], true);
+            assert!(image["action"].is_null());
+            assert!(image["destination"].is_null());
+        } else {
+            assert_eq!(image["path"], duplicate.to_str().unwrap());
+            assert_eq!(image["keep"], false);
+            assert_eq!(image["action"], "move");
+            assert_eq!(image["destination"], destination.to_str().unwrap());
+        }
+    }
+
+    let mut csv = Vec::new();
+    write_report(&mut csv, &groups, Action::Move, &plan, ReportFormat::Csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "group,match,hash,path,size,width,height,keep,action,destination,transform,overlap"
+    );
+    let quoted = |path: &Path| format!("\"{}\"", path.display().to_string().replace('"', "\"\""));
+    let hash = group["hash"].as_str().unwrap();
+    let keeper_row = format!(
+        "0,exact,{},{},{},256,192,true,,,,",
+        hash,
+        quoted(keeper),
+        images[0]["size"]
+    );
+    let duplicate_row = format!(
+        "0,exact,{},{},{},256,192,false,move,{},none,",
+        hash,
+        quoted(duplicate),
+        images[0]["size"],
+        quoted(destination)
+    );
+    let mut rows = lines[1..].to_vec();
+    rows.sort();
+    let mut expected = vec![keeper_row, duplicate_row];
+    expected.sort();
+    assert_eq!(rows, expected);
+}
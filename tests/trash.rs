@@ -0,0 +1,33 @@
+// The trash is a freedesktop.org thing
+#![cfg(unix)]
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+
+use image_dedup_cli::trash;
+
+#[test]
+fn trash_info_leads_back_to_names_that_arent_utf8() {
+    let dir = tempfile::tempdir().unwrap();
+    // The only test in this binary, so there's no one to race with
+    env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+    let images = fs::canonicalize(dir.path()).unwrap().join("images");
+    fs::create_dir(&images).unwrap();
+    let path = images.join(OsStr::from_bytes(b"caf\xe9.png"));
+    fs::write(&path, "not really a png").unwrap();
+
+    let trashed = trash::move_to_trash(&path).unwrap();
+    assert!(!path.exists());
+    let mut info_name = trashed.file_name().unwrap().to_os_string();
+    info_name.push(".trashinfo");
+    let info_path = dir.path().join("data/Trash/info").join(info_name);
+    let info = fs::read_to_string(&info_path).unwrap();
+    let expected = format!("Path={}/caf%E9.png", images.display());
+    assert_eq!(info.lines().nth(1), Some(expected.as_str()), "{}", info);
+
+    trash::restore(&trashed, &path).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"not really a png");
+    assert!(!info_path.exists());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs;
+
+use image_dedup_cli::{DedupOptions, MatchKind, Watch};
+
+use common::{pattern, write_fixtures};
+
+#[test]
+fn matches_arrivals_against_existing_images() {
+    let dir = tempfile::tempdir().unwrap();
+    let fixtures = dir.path().join("fixtures");
+    write_fixtures(&fixtures);
+    let watched = dir.path().join("watched");
+    fs::create_dir(&watched).unwrap();
+    fs::copy(fixtures.join("a.png"), watched.join("a.png")).unwrap();
+
+    let options = DedupOptions {
+        threshold: 10,
+        ..DedupOptions::default()
+    };
+    let (mut watch, skipped) = Watch::new(&watched, options, None, &()).unwrap();
+    assert!(skipped.is_empty());
+    assert_eq!(watch.len(), 1);
+    let watched = watch.root().to_path_buf();
+
+    fs::copy(fixtures.join("a_copy.png"), watched.join("a_copy.png")).unwrap();
+    let found = watch.arrived(&watched.join("a_copy.png"));
+    assert_eq!(found.groups.len(), 1);
+    assert_eq!(found.groups[0].kind, MatchKind::Exact);
+    assert_eq!(watch.len(), 1);
+
+    // Something new is kept around for the next arrivals
+    pattern(256, 192, 5).save(watched.join("b.png")).unwrap();
+    assert!(watch.arrived(&watched.join("b.png")).groups.is_empty());
+    assert_eq!(watch.len(), 2);
+
+    fs::copy(fixtures.join("notes.txt"), watched.join("notes.txt")).unwrap();
+    let found = watch.arrived(&watched.join("notes.txt"));
+    assert!(found.groups.is_empty() && found.skipped.is_empty());
+
+    // Largest is kept by default, so the re-encoded copy takes the original's place
+    fs::create_dir(watched.join("sub")).unwrap();
+    fs::copy(
+        fixtures.join("a_reencoded.jpg"),
+        watched.join("sub").join("a.jpg"),
+    )
+    .unwrap();
+    let found = watch.arrived(&watched.join("sub"));
+    assert!(found.groups.is_empty(), "not recursive");
+    fs::rename(watched.join("sub").join("a.jpg"), watched.join("a.jpg")).unwrap();
+    watch.removed(&watched.join("sub").join("a.jpg"));
+    let found = watch.arrived(&watched.join("a.jpg"));
+    assert_eq!(found.groups.len(), 1);
+    assert_eq!(found.groups[0].kind, MatchKind::Perceptual);
+    assert_eq!(found.groups[0].keeper().path, watched.join("a.jpg"));
+
+    // What b.png looked like before it was overwritten is forgotten
+    pattern(256, 192, 7).save(watched.join("b.png")).unwrap();
+    assert!(watch.arrived(&watched.join("b.png")).groups.is_empty());
+    pattern(256, 192, 5).save(watched.join("c.png")).unwrap();
+    assert!(watch.arrived(&watched.join("c.png")).groups.is_empty());
+    assert_eq!(watch.len(), 3);
+
+    watch.removed(&watched.join("b.png"));
+    watch.removed(&watched.join("c.png"));
+    assert_eq!(watch.len(), 1);
+}